@@ -3,15 +3,24 @@ use std::borrow::{Cow, Borrow};
 use serde::{Deserialize, Serialize};
 use eframe::egui::{Button, Color32, Context, Direction, FontData, FontDefinitions, FontFamily,
                    Label, Layout, RichText, TextStyle, TopBottomPanel, Ui, Visuals, FontId,
-                   TextBuffer, Stroke, Vec2, Rgba, Window, Rect};
+                   TextBuffer, Stroke, Vec2, Rgba, Window, Rect, TextEdit};
 use eframe::epi::{Frame, DummyStorage};
 use eframe::epi;
 use epi::App;
 use egui::{ScrollArea};
 use eframe::epi::Storage;
 use std::time::Duration;
-use snow_treading::{load_file, save_file};
-use crate::note::{Note, NoteWarp};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use snow_treading::{NoteId, NoteStore, Notebook, StorageError};
+use snow_treading::cloud::{CloudError, CloudProvider, GoogleCredentials, GoogleDrive, Endpoints, S3Config, SyncEngine, SyncReport, Syncer, WebDavConfig};
+use snow_treading::history::{Edit, History};
+use snow_treading::toast::{Level, Notifier};
+use snow_treading::model::SortKey;
+use snow_treading::storage::{self, lock, Autosave, Instance, InstanceLock, Location, MemoryStore, Report, SharedStore, Source, StorageBackend, StoreWatcher, Worker};
+use crate::config::store_config;
+use crate::note::{local_time, note_color, unsaved_buttons, NoteWarp, Unsaved};
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::ops::Range;
@@ -20,17 +29,91 @@ use reqwest::Client;
 
 
 
+// watches wherever the store keeps its notes, if it keeps them on disk
+fn watch(store: &SharedStore, notifier: &Notifier) -> Option<StoreWatcher> {
+    let location = lock(store).location()?;
+    match StoreWatcher::new(&location, notifier.clone()) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            warn!("[Snow]: changes by other programs will not be noticed: {}", e);
+            None
+        }
+    }
+}
+
+// how long closing the app waits for pending writes
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// starts syncing with wherever the config says, if anywhere
+fn start_sync(provider: &CloudProvider, minutes: u32, store: &SharedStore, notifier: &Notifier) -> Option<Syncer> {
+    let engine = provider.connect()
+        .and_then(|remote| remote.map(|remote| SyncEngine::new(remote).map_err(CloudError::from)).transpose());
+    match engine {
+        Ok(engine) => engine.map(|engine| Syncer::spawn(engine, store.clone(), notifier.clone(), Duration::from_secs(minutes.max(1) as u64 * 60))),
+        Err(e) => {
+            error!("[Snow]: could not start syncing: {}", e);
+            notifier.error(format!("could not start syncing: {}", e));
+            None
+        }
+    }
+}
+
 // simple config struct
 #[derive(Serialize, Deserialize)]
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct AppConfig {
     pub(crate) dark_mode: bool,
-    bookmark_panel: bool
+    bookmark_panel: bool,
+    #[serde(default)]
+    backend: StorageBackend,
+    #[serde(default)]
+    sort_key: SortKey,
+    #[serde(default)]
+    sort_ascending: bool,
+    // notes are purged from the trash after this many days
+    #[serde(default = "default_trash_retention")]
+    trash_retention_days: u32,
+    // how many revisions are kept per note, 0 keeps all of them
+    #[serde(default = "default_revision_limit")]
+    pub(crate) revision_limit: usize,
+    // notes are written this many seconds after they were last changed
+    #[serde(default = "default_autosave")]
+    autosave: bool,
+    #[serde(default = "default_autosave_delay")]
+    autosave_delay: f32,
+    // where the notes are kept, the platform default (or next to the executable) if unset
+    #[serde(default)]
+    pub(crate) data_dir: Option<PathBuf>,
+    // where the notes are synced to and how often
+    #[serde(default)]
+    cloud: CloudProvider,
+    #[serde(default = "default_sync_minutes")]
+    sync_minutes: u32,
+}
+
+fn default_sync_minutes() -> u32 {
+    5
+}
+
+fn default_autosave() -> bool {
+    true
+}
+
+fn default_autosave_delay() -> f32 {
+    2.
+}
+
+fn default_trash_retention() -> u32 {
+    30
+}
+
+fn default_revision_limit() -> usize {
+    50
 }
 
 impl AppConfig {
     fn new() -> Self {
-        AppConfig { dark_mode: true, bookmark_panel: true }
+        AppConfig { dark_mode: true, bookmark_panel: true, ..Default::default() }
     }
 }
 
@@ -38,7 +121,17 @@ impl Default for AppConfig {
     fn default() -> Self {
         Self {
             dark_mode: true,
-            bookmark_panel: true
+            bookmark_panel: true,
+            backend: StorageBackend::default(),
+            sort_key: SortKey::default(),
+            sort_ascending: false,
+            trash_retention_days: default_trash_retention(),
+            revision_limit: default_revision_limit(),
+            autosave: default_autosave(),
+            autosave_delay: default_autosave_delay(),
+            data_dir: None,
+            cloud: CloudProvider::default(),
+            sync_minutes: default_sync_minutes(),
         }
     }
 }
@@ -51,10 +144,49 @@ pub struct SnowApp {
     // an empty label for text inputs?
     pub(crate) empty_label: String,
     config: AppConfig,
+    // every read and write of notes goes through here
+    // only read from here, writes go through the worker
+    store: SharedStore,
+    worker: Worker,
+    // notices when the notes are changed by another program
+    watcher: Option<StoreWatcher>,
+    autosave: Autosave,
+    notebook: Notebook,
+    // undo/redo of note edits
+    history: History,
     note_warp: NoteWarp,
     config_window: bool,
-    note: Option<usize>,
-    confirmation_window: (bool, String),
+    trash_window: bool,
+    // asks what to do with unsaved notes before quitting
+    quit_window: bool,
+    // the note open in the editor
+    note: Option<NoteId>,
+    // toasts in the bottom right corner
+    notifier: Notifier,
+    // set when the note store could not be loaded, shows the recovery screen instead of the app
+    recovery: Option<StorageError>,
+    // text input for the markdown vault directory in the config window
+    vault_dir: String,
+    // where the config and the notes are kept
+    location: Location,
+    // text input for moving the data dir in the config window
+    data_dir: String,
+    // keeps other copies of the app from writing the notes while we run
+    _instance: Option<InstanceLock>,
+    // set when another launch of the app asked us to show ourselves
+    shown: Arc<AtomicBool>,
+    // another copy of the app owns the notes, nothing is written
+    read_only: bool,
+    // syncs the notes in the background while a cloud provider is set up
+    syncer: Option<Syncer>,
+    // the google login running in the browser, if any
+    login: Option<mpsc::Receiver<Result<(), CloudError>>>,
+    // text inputs for the google oauth client in the config window
+    google: GoogleCredentials,
+    // text inputs for the webdav server in the config window
+    webdav: WebDavConfig,
+    // text inputs for the s3 bucket in the config window
+    s3: S3Config,
 }
 
 impl App for SnowApp {
@@ -80,16 +212,40 @@ impl App for SnowApp {
             }
         }
 
-        if self.note_warp.bool {
-            self.note_warp.note_window(ctx, self.note.unwrap());
+        // never show the actual app on top of a store we could not read
+        if self.recovery.is_some() {
+            self.render_recovery_screen(ctx, frame);
+            return;
         }
 
+        if self.read_only {
+            self.render_read_only_banner(ctx);
+        }
+        if self.shown.swap(false, Ordering::SeqCst) {
+            // egui can not bring the window to the front, so at least say where the app went
+            self.notifier.info("Snow Treading is already running, here it is!");
+        }
 
-        if self.note_warp.confirmation_window.0 {
-            Window::new("saved!")
-                .collapsible(false)
-                .show(ctx, |ui| {});
+        self.handle_undo_keys(ctx);
 
+        if let (true, Some(id)) = (self.note_warp.editor_open, self.note) {
+            self.note_warp.note_window(ctx, &mut self.notebook, id, &self.worker, &mut self.history, self.config.revision_limit);
+        }
+
+        self.handle_reports();
+        self.handle_login();
+        self.handle_sync_results();
+        self.reload_external_changes();
+        self.run_autosave(ctx);
+
+        self.render_toasts(ctx);
+
+        if self.trash_window {
+            self.trash_window(ctx);
+        }
+
+        if self.quit_window {
+            self.quit_window(ctx, frame);
         }
 
         if self.config_window {
@@ -99,29 +255,35 @@ impl App for SnowApp {
         // call top panel render
         self.render_top_panel(ctx, frame);
 
-        // side panel containing quick access to recent or bookmarked notes TODO: add timestamps and sort
+        // side panel containing quick access to recent or bookmarked notes
         self.render_bookmarks_panel(ctx);
 
         // TODO: EVERYTHING
         self.center_panel_render(ctx);
     }
 
-    fn setup(&mut self, ctx: &Context, _frame: &epi::Frame, _storage: Option<&dyn epi::Storage>) {
+    fn setup(&mut self, ctx: &Context, frame: &epi::Frame, _storage: Option<&dyn epi::Storage>) {
         self.configure_fonts(ctx);
+        // toasts can come from other threads, they should show up without waiting for input
+        let frame = frame.clone();
+        self.notifier.set_waker(move || frame.request_repaint());
     }
 
+    // only empties the trash, notes are written by the autosave or by hand
     fn save(&mut self, _storage: &mut dyn Storage) {
-        save_file("data", &self.note_warp.notes);
+        self.purge_expired();
     }
 
     fn on_exit(&mut self) {
-        save_file("data", &self.note_warp.notes);
+        self.save_notes();
+        self.flush();
     }
 
     fn name(&self) -> &str {
         "Snow Window"
     }
 
+    // how often the expired trash is emptied
     fn auto_save_interval(&self) -> Duration {
         Duration::new(300, 0)
     }
@@ -130,26 +292,538 @@ impl App for SnowApp {
 
 impl SnowApp {
 
-    pub fn new() -> SnowApp{
+    pub fn new(config: AppConfig, location: Location, instance: Option<Instance>) -> SnowApp{
+
+        let vault_dir = match &config.backend {
+            StorageBackend::Vault(dir) => Some(dir.clone()),
+            _ => storage::default_vault_dir().ok(),
+        };
+        let vault_dir = vault_dir.map(|dir| dir.display().to_string()).unwrap_or_default();
 
-        let config: AppConfig = confy::load("Snow Window").unwrap_or_default();
+        let opened = storage::open(&config.backend).and_then(|mut store| {
+            let notes = store.load_all()?;
+            Ok((store, notes))
+        });
+        let (store, notes, recovery) = match opened {
+            Ok((store, notes)) => (store, notes, None),
+            Err(e) => {
+                error!("[Snow]: could not load notes: {}", e);
+                // the json store can still be repaired from the recovery screen
+                let store: Box<dyn NoteStore> = match config.backend {
+                    StorageBackend::Json => storage::open(&StorageBackend::Json).unwrap_or_else(|_| Box::new(MemoryStore::new())),
+                    _ => Box::new(MemoryStore::new()),
+                };
+                (store, Vec::new(), Some(e))
+            }
+        };
 
-        SnowApp {
+        let notifier = Notifier::new();
+        let read_only = storage::is_read_only();
+        let (instance, shown) = match instance {
+            Some(Instance::Primary(mut lock)) => {
+                let shown = lock.listen(notifier.clone());
+                (Some(lock), shown)
+            }
+            _ => (None, Arc::default()),
+        };
+        if read_only {
+            notifier.warning("Snow Treading is already running, this window is read-only");
+        }
+        let autosave = Autosave::new(Duration::from_secs_f32(config.autosave_delay), config.autosave);
+        let store = storage::shared(store);
+        let worker = Worker::spawn(store.clone(), notifier.clone());
+        let watcher = watch(&store, &notifier);
+        // an empty store in place of notes that failed to load would remove them on the other side
+        let syncer = if read_only || recovery.is_some() { None } else { start_sync(&config.cloud, config.sync_minutes, &store, &notifier) };
+        let google = match &config.cloud {
+            CloudProvider::GoogleDrive(credentials) => credentials.clone(),
+            _ => GoogleCredentials::default(),
+        };
+        let webdav = match &config.cloud {
+            CloudProvider::WebDav(webdav) => webdav.clone(),
+            _ => WebDavConfig::default(),
+        };
+        let s3 = match &config.cloud {
+            CloudProvider::S3(s3) => s3.clone(),
+            _ => S3Config::default(),
+        };
+        let mut app = SnowApp {
             label: String::from("Hallo, Snowy World"),
             empty_label: "".to_owned(),
             config,
-            note_warp: NoteWarp {
-                notes: load_file("data"),
-                confirmation_window: (false, "".to_string()),
-                bool: false,
-                closing_window: false
-            },
+            store,
+            worker,
+            watcher,
+            autosave,
+            notebook: Notebook::new(notes),
+            history: History::new(),
+            note_warp: NoteWarp { notifier: notifier.clone(), ..NoteWarp::default() },
             config_window: false,
+            trash_window: false,
+            quit_window: false,
             note: None,
-            confirmation_window: (false, "".to_string()),
+            notifier,
+            recovery,
+            vault_dir,
+            data_dir: location.data_dir.display().to_string(),
+            location,
+            _instance: instance,
+            shown,
+            read_only,
+            syncer,
+            login: None,
+            google,
+            webdav,
+            s3,
+        };
+        app.purge_expired();
+        app
+    }
+
+    // removes notes that sat in the trash for longer than configured
+    fn purge_expired(&mut self) {
+        if self.recovery.is_some() || self.read_only {
+            return;
+        }
+        let retention = chrono::Duration::days(self.config.trash_retention_days as i64);
+        for id in self.notebook.purge_expired(retention) {
+            self.history.forget(id);
+            self.worker.delete(id);
+        }
+    }
+
+    // moves a note to the trash, closing it if it is open
+    fn delete_note(&mut self, id: NoteId) {
+        if !self.notebook.delete(id) {
+            return;
+        }
+        self.history.record(id, Edit::Delete);
+        if self.note == Some(id) {
+            self.note_warp.editor_open = false;
+            self.note = None;
+        }
+        self.persist_note(id);
+    }
+
+    fn restore_note(&mut self, id: NoteId) {
+        if self.notebook.restore(id) {
+            self.persist_note(id);
+        }
+    }
+
+    fn purge_note(&mut self, id: NoteId) {
+        if self.notebook.purge(id).is_some() {
+            self.history.forget(id);
+            self.worker.delete(id);
+        }
+    }
+
+    // writes a single note to the store in the background
+    fn persist_note(&mut self, id: NoteId) {
+        if let Some(note) = self.notebook.get(id) {
+            self.worker.upsert(note.clone());
+        }
+    }
+
+    // what the storage worker got done since the last frame, failures were already shown as toasts
+    fn handle_reports(&mut self) {
+        for report in self.worker.reports() {
+            match report {
+                Report::Saved(note) => self.notebook.mark_saved_as(note),
+                Report::Reloaded(notes) => self.apply_external_changes(notes),
+                Report::Deleted(_) | Report::Failed { .. } => {}
+            }
+        }
+    }
+
+    // logs in to google drive in the browser unless there is a token already, then starts syncing
+    fn connect_google(&mut self) {
+        let credentials = self.google.clone();
+        let drive = match GoogleDrive::new(credentials.clone(), Endpoints::default()) {
+            Ok(drive) => drive,
+            Err(e) => {
+                self.notifier.error(format!("could not connect to Google Drive: {}", e));
+                return;
+            }
+        };
+        self.config.cloud = CloudProvider::GoogleDrive(credentials);
+        if drive.is_logged_in() {
+            self.store_confy();
+            self.restart_sync();
+            return;
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let notifier = self.notifier.clone();
+        tokio::spawn(async move {
+            let result = drive.login(|url| {
+                if let Err(e) = webbrowser::open(url) {
+                    warn!("[Snow]: could not open the browser, log in at {}: {}", url, e);
+                }
+            }).await;
+            let _ = tx.send(result);
+            notifier.wake();
+        });
+        self.login = Some(rx);
+        self.notifier.info("log in to Google Drive in your browser");
+    }
+
+    // (re)starts syncing with the provider in the config, never while the notes are being recovered
+    fn restart_sync(&mut self) {
+        self.syncer = match self.recovery {
+            Some(_) => None,
+            None => start_sync(&self.config.cloud, self.config.sync_minutes, &self.store, &self.notifier),
+        };
+    }
+
+    // webdav and s3 need no login besides their keys, problems show up with the first sync
+    fn connect(&mut self, provider: CloudProvider) {
+        self.config.cloud = provider;
+        self.restart_sync();
+        if self.syncer.is_some() {
+            self.store_confy();
+        } else {
+            self.config.cloud = CloudProvider::None;
+        }
+    }
+
+    fn handle_login(&mut self) {
+        let result = match self.login.as_ref().map(|login| login.try_recv()) {
+            Some(Ok(result)) => result,
+            Some(Err(mpsc::TryRecvError::Empty)) | None => return,
+            Some(Err(mpsc::TryRecvError::Disconnected)) => Err(CloudError::Auth("the login was interrupted".to_owned())),
+        };
+        self.login = None;
+        match result {
+            Ok(()) => {
+                self.store_confy();
+                // the new login might be another account
+                self.reset_sync_state();
+                self.restart_sync();
+                self.notifier.success("logged in to Google Drive!");
+            }
+            Err(e) => {
+                error!("[Snow]: could not log in: {}", e);
+                self.config.cloud = CloudProvider::None;
+                self.notifier.error(format!("could not log in: {}", e));
+            }
+        }
+    }
+
+    fn disconnect_sync(&mut self) {
+        self.syncer = None;
+        if let CloudProvider::GoogleDrive(credentials) = &self.config.cloud {
+            if let Ok(drive) = GoogleDrive::new(credentials.clone(), Endpoints::default()) {
+                tokio::spawn(async move {
+                    if let Err(e) = drive.logout().await {
+                        warn!("[Snow]: could not forget the Google Drive login: {}", e);
+                    }
+                });
+            }
+        }
+        self.config.cloud = CloudProvider::None;
+        self.store_confy();
+        self.reset_sync_state();
+        self.notifier.info("sync turned off");
+    }
+
+    fn reset_sync_state(&self) {
+        if let Err(e) = SyncEngine::reset() {
+            warn!("[Snow]: could not forget what was synced: {}", e);
         }
     }
 
+    // shows what the syncer got done, the store already has the synced notes
+    fn handle_sync_results(&mut self) {
+        let results = match &self.syncer {
+            Some(syncer) => syncer.results(),
+            None => return,
+        };
+        for result in results {
+            match result {
+                Ok(report) => self.apply_sync(report),
+                Err(CloudError::Auth(message)) => {
+                    self.notifier.error(format!("sync stopped, the login was refused: {}", message));
+                    self.syncer = None;
+                }
+                Err(e) => {
+                    self.notifier.error(format!("could not sync: {}", e));
+                }
+            }
+        }
+    }
+
+    fn apply_sync(&mut self, report: SyncReport) {
+        let open = if self.note_warp.editor_open { self.note } else { None };
+        for note in report.downloaded {
+            // never pull the rug out from under an edit that is not saved yet
+            if open == Some(note.id) || self.notebook.is_dirty(note.id) {
+                self.notifier.warning(format!("'{}' was changed on another device", note.title));
+                self.note_warp.conflicts.insert(note.id, note);
+                continue;
+            }
+            self.notebook.insert(note.clone());
+            self.notebook.mark_saved_as(note);
+        }
+        for id in report.removed {
+            if open != Some(id) && !self.notebook.is_dirty(id) {
+                self.notebook.purge(id);
+                self.history.forget(id);
+            }
+        }
+        if !report.merged.is_empty() {
+            self.notifier.info(format!("merged the changes to {} note(s) from two devices", report.merged.len()));
+        }
+        if !report.conflicts.is_empty() {
+            self.notifier.warning(format!("{} note(s) were changed on two devices, open them to compare", report.conflicts.len()));
+            self.note_warp.copies.extend(report.conflicts);
+        }
+        if !report.failed.is_empty() {
+            self.notifier.error(format!("{} note(s) could not be synced, see the log", report.failed.len()));
+        }
+    }
+
+    // ctrl+z / ctrl+shift+z, on the note in the editor or else on the note that was edited last
+    fn handle_undo_keys(&mut self, ctx: &Context) {
+        let (undo, redo) = {
+            let input = ctx.input();
+            let z = input.modifiers.command && input.key_pressed(egui::Key::Z);
+            (z && !input.modifiers.shift, z && input.modifiers.shift)
+        };
+        if !undo && !redo {
+            return;
+        }
+        let id = match (self.note_warp.editor_open, self.note) {
+            (true, Some(id)) => id,
+            _ => match self.history.last() {
+                Some(id) => id,
+                None => return,
+            },
+        };
+        // otherwise the focused text field would undo on its own as well
+        ctx.memory().stop_text_input();
+
+        let edit = if undo {
+            self.history.undo(&mut self.notebook, id)
+        } else {
+            self.history.redo(&mut self.notebook, id)
+        };
+        // trashing is saved right away, so taking it back is as well
+        if let Some(Edit::Delete) = edit {
+            if self.note == Some(id) && self.notebook.get(id).is_some_and(|note| note.is_deleted()) {
+                self.note_warp.editor_open = false;
+                self.note = None;
+            }
+            self.persist_note(id);
+        }
+    }
+
+    // the store is read again on the worker, the notes come back through `handle_reports`
+    fn reload_external_changes(&mut self) {
+        if self.watcher.as_ref().is_some_and(|watcher| watcher.take_changed()) && self.recovery.is_none() {
+            self.worker.reload();
+        }
+    }
+
+    // picks up notes that were changed by another program. Notes nobody is working on are
+    // simply reloaded, for the others the editor asks what to do
+    fn apply_external_changes(&mut self, theirs: Vec<snow_treading::Note>) {
+        if self.recovery.is_some() {
+            return;
+        }
+        let open = if self.note_warp.editor_open { self.note } else { None };
+        let (mut reloaded, mut conflicts) = (0, Vec::new());
+        for note in &theirs {
+            let id = note.id;
+            let ours = match self.notebook.get(id) {
+                Some(ours) => ours,
+                None => {
+                    self.notebook.insert(note.clone());
+                    self.notebook.mark_saved_as(note.clone());
+                    reloaded += 1;
+                    continue;
+                }
+            };
+            let same = |other: &snow_treading::Note| other.same_content(note) && other.deleted == note.deleted;
+            // our own write, e.g. one the worker did not report yet
+            if same(ours) {
+                self.notebook.mark_saved_as(note.clone());
+                continue;
+            }
+            // not changed since we last saw it
+            if self.notebook.saved(id).is_some_and(same) {
+                continue;
+            }
+            if open == Some(id) || self.notebook.is_dirty(id) {
+                conflicts.push(ours.title.clone());
+                self.note_warp.conflicts.insert(id, note.clone());
+            } else {
+                self.notebook.insert(note.clone());
+                self.notebook.mark_saved_as(note.clone());
+                reloaded += 1;
+            }
+        }
+        // notes removed elsewhere are only dropped here if nothing would get lost
+        let removed: Vec<NoteId> = self.notebook.iter()
+            .map(|note| note.id)
+            .filter(|id| self.notebook.saved(*id).is_some() && !theirs.iter().any(|note| note.id == *id))
+            .filter(|id| open != Some(*id) && !self.notebook.is_dirty(*id))
+            .collect();
+        for id in removed {
+            self.notebook.purge(id);
+            self.history.forget(id);
+            reloaded += 1;
+        }
+
+        if reloaded > 0 {
+            info!("[Snow]: reloaded {} notes changed by another program", reloaded);
+            self.notifier.info(format!("reloaded {} changed note(s)", reloaded));
+        }
+        for title in conflicts {
+            self.notifier.warning(format!("'{}' was changed outside of the app", title));
+        }
+    }
+
+    // writes notes in the background once they were not changed for a moment
+    fn run_autosave(&mut self, ctx: &Context) {
+        if self.recovery.is_some() || self.read_only {
+            return;
+        }
+
+        for id in self.notebook.dirty() {
+            if let Some(note) = self.notebook.get(id) {
+                self.autosave.watch(note);
+            }
+        }
+        for id in self.autosave.due() {
+            // might have been saved by hand in the meantime, and conflicts wait for the user
+            if !self.notebook.is_dirty(id) || self.note_warp.conflicts.contains_key(&id) {
+                continue;
+            }
+            if let Some(note) = self.notebook.get(id) {
+                self.worker.upsert(note.clone());
+            }
+        }
+        // nothing else would trigger a frame once typing stops
+        if self.autosave.next_due().is_some() {
+            ctx.request_repaint();
+        }
+    }
+
+    /// Writes all notes to disk, unless we are still in recovery mode
+    /// (saving then would overwrite the data the user is trying to recover)
+    /// or another copy of the app owns the notes.
+    fn save_notes(&mut self) {
+        if self.recovery.is_some() || self.read_only {
+            return;
+        }
+        // notes changed by another program keep that version until the user picks one
+        let conflicts = &self.note_warp.conflicts;
+        let notes = self.notebook.iter().map(|note| conflicts.get(&note.id).unwrap_or(note).clone()).collect();
+        self.worker.save_all(notes);
+    }
+
+    // waits for the worker to write everything that was sent to it so far
+    fn flush(&mut self) -> bool {
+        let flushed = self.worker.flush(FLUSH_TIMEOUT);
+        if !flushed {
+            error!("[Snow]: notes were still being written after {:?}", FLUSH_TIMEOUT);
+        }
+        self.handle_reports();
+        flushed
+    }
+
+    // Save / Discard / Cancel for all unsaved notes before the app is closed
+    fn quit_window(&mut self, ctx: &Context, frame: &Frame) {
+        let dirty = self.notebook.dirty();
+        let mut choice = None;
+        Window::new("Unsaved changes")
+            .id(egui::Id::new("quit_window"))
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("{} note(s) have changes that were not saved yet:", dirty.len()));
+                for id in &dirty {
+                    if let Some(note) = self.notebook.get(*id) {
+                        ui.label(RichText::new(format!("• {}", note.title)).small());
+                    }
+                }
+                ui.add_space(5.);
+                choice = unsaved_buttons(ui);
+            });
+
+        match choice {
+            Some(Unsaved::Save) => {
+                self.save_notes();
+                self.flush();
+                if self.notebook.dirty().is_empty() {
+                    frame.quit();
+                } else {
+                    self.notifier.error("could not save, see the log");
+                }
+            }
+            Some(Unsaved::Discard) => {
+                for id in dirty {
+                    self.notebook.discard(id);
+                }
+                frame.quit();
+            }
+            Some(Unsaved::Cancel) => {}
+            None => return,
+        }
+        self.quit_window = false;
+    }
+
+    // shown instead of the app when the note store could not be loaded
+    fn render_recovery_screen(&mut self, ctx: &Context, frame: &Frame) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.add_space(30.);
+            ui.vertical_centered(|ui| {
+                ui.label(RichText::new("Your notes could not be loaded").heading().strong());
+                ui.add_space(10.);
+                if let Some(e) = &self.recovery {
+                    ui.label(e.to_string());
+                }
+                ui.add_space(20.);
+
+                // backups and quarantining only exist for the json file
+                let json = self.config.backend == StorageBackend::Json;
+                let restore_btn = ui.add_enabled(json && storage::has_backup("data"), Button::new("Restore from backup"))
+                    .on_hover_text("Replace the data file with the newest backup");
+                let fresh_btn = ui.add_enabled(json, Button::new("Start with an empty store"))
+                    .on_hover_text("The unreadable file is kept next to the new one");
+                let quit_btn = ui.add(Button::new("Quit"));
+
+                if restore_btn.clicked() {
+                    self.recovery = storage::list_backups("data")
+                        .and_then(|backups| match backups.first() {
+                            Some(newest) => storage::restore_backup("data", newest),
+                            None => Ok(()),
+                        })
+                        .and_then(|_| lock(&self.store).load_all())
+                        .map(|notes| self.notebook = Notebook::new(notes))
+                        .err();
+                }
+                if fresh_btn.clicked() {
+                    self.recovery = match storage::quarantine("data") {
+                        // the file is already gone, nothing to move away
+                        Ok(_) | Err(StorageError::Missing(_)) => lock(&self.store).load_all()
+                            .map(|notes| self.notebook = Notebook::new(notes))
+                            .err(),
+                        Err(e) => Some(e),
+                    };
+                }
+                // syncing waits until the notes are recovered
+                if (restore_btn.clicked() || fresh_btn.clicked()) && self.recovery.is_none() && !self.read_only {
+                    self.restart_sync();
+                }
+                if quit_btn.clicked() {
+                    frame.quit();
+                }
+            });
+        });
+    }
+
     fn render_top_panel(&mut self, ctx: &Context, frame: &Frame) {
         TopBottomPanel::top("header").show(ctx, |ui| {
             ui.add_space(3.3);
@@ -198,6 +872,9 @@ impl SnowApp {
                             RichText::new("⚙️")
                                 .heading()))
                         .on_hover_text(RichText::new("Config"));
+                    let trash_btn = ui
+                        .add(Button::new(RichText::new("🗑").heading()))
+                        .on_hover_text(RichText::new("Trash"));
                     let theme_btn = ui
                         .add(Button::new(
                             RichText::new(if self.config.dark_mode {"Dark"} else {"Light"})
@@ -207,8 +884,13 @@ impl SnowApp {
 
                     // add logic to the close button
                     if close_btn.clicked() {
-                        dbg!("Closing app!");
-                        frame.quit();
+                        // read-only changes can not be saved anyway
+                        if self.read_only || self.notebook.dirty().is_empty() {
+                            dbg!("Closing app!");
+                            frame.quit();
+                        } else {
+                            self.quit_window = true;
+                        }
                     };
 
                     //config btn logic
@@ -217,6 +899,10 @@ impl SnowApp {
                         dbg!(self.config_window);
                     }
 
+                    if trash_btn.clicked() {
+                        self.trash_window = !self.trash_window;
+                    }
+
                     //add logic to the theme button
                     if theme_btn.clicked() {
                         self.config.dark_mode = !self.config.dark_mode;
@@ -230,6 +916,52 @@ impl SnowApp {
         });
     }
 
+    // stays on top for as long as another copy of the app owns the notes
+    fn render_read_only_banner(&mut self, ctx: &Context) {
+        TopBottomPanel::top("read_only").show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.label(RichText::new("⚠ Snow Treading is already running in another window. \
+                    These notes are read-only, changes made here will not be saved.")
+                    .color(Color32::from_rgb(230, 160, 40)));
+            });
+        });
+    }
+
+    // stacked toasts in the bottom right corner, newest at the bottom
+    fn render_toasts(&mut self, ctx: &Context) {
+        let toasts = self.notifier.active();
+        if toasts.is_empty() {
+            return;
+        }
+        egui::Area::new("toasts")
+            .anchor(egui::Align2::RIGHT_BOTTOM, Vec2::new(-10., -10.))
+            .show(ctx, |ui| {
+                for toast in &toasts {
+                    let (icon, color) = match toast.level {
+                        Level::Info => ("ℹ", Color32::from_rgb(100, 160, 230)),
+                        Level::Success => ("✔", Color32::from_rgb(90, 200, 120)),
+                        Level::Warning => ("⚠", Color32::from_rgb(230, 160, 60)),
+                        Level::Error => ("❌", Color32::from_rgb(230, 90, 90)),
+                    };
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.set_max_width(280.);
+                        ui.horizontal(|ui| {
+                            ui.label(RichText::new(icon).color(color));
+                            ui.label(&toast.text);
+                            if ui.small_button("✖").on_hover_text("Dismiss").clicked() {
+                                self.notifier.dismiss(toast.id);
+                            }
+                        });
+                    });
+                    ui.add_space(4.);
+                }
+            });
+        // keep repainting so the toasts disappear on time
+        if toasts.iter().any(|toast| toast.timeout.is_some()) {
+            ctx.request_repaint();
+        }
+    }
+
     fn render_bookmarks_panel(&mut self, ctx: &Context) {
 
         // let side panel for the note bookmarks (for now?)
@@ -242,34 +974,75 @@ impl SnowApp {
                     );
                     ui.add_space(5.);
                 });
+
+                // sort selector, remembered in the config
+                ui.horizontal(|ui| {
+                    let mut sort_key = self.config.sort_key;
+                    egui::ComboBox::from_id_source("bookmarks_sort")
+                        .selected_text(sort_key.label())
+                        .show_ui(ui, |ui| {
+                            for key in SortKey::ALL {
+                                ui.selectable_value(&mut sort_key, key, key.label());
+                            }
+                        });
+                    let order_btn = ui.button(if self.config.sort_ascending { "⬆" } else { "⬇" })
+                        .on_hover_text(if self.config.sort_ascending { "Ascending" } else { "Descending" });
+
+                    if sort_key != self.config.sort_key || order_btn.clicked() {
+                        self.config.sort_key = sort_key;
+                        self.config.sort_ascending ^= order_btn.clicked();
+                        self.store_confy();
+                    }
+                });
                 ui.add_space(5.);
 
                 // scroll are for the actual bookmarks
                 ScrollArea::vertical().show(ui, |ui| {
 
-                    // iterate and add the notes
-                    for i in 0..self.note_warp.notes.len() {
+                    let mut deleted = None;
+
+                    // iterate and add the notes, in the selected order
+                    for i in self.notebook.sorted_indices(self.config.sort_key, self.config.sort_ascending) {
                         let scroll_note = ui.add_enabled_ui(true, |ui| {
                             // sets the colors of the indent/separator to fit the current note
-                            ui.visuals_mut().widgets.noninteractive.bg_stroke = Stroke::new(2.3, self.note_warp.notes[i].get_note_color());
+                            ui.visuals_mut().widgets.noninteractive.bg_stroke = Stroke::new(2.3, note_color(&self.notebook.notes()[i]));
                             ui.separator();
                             ui.add_space(3.);
                             // adds the note title
                             ui.indent("note_title", |ui| {
-                                let title_edit = ui.text_edit_singleline(&mut self.note_warp.notes[i].title);
+                                let old_title = self.notebook.notes()[i].title.clone();
+                                let title_edit = ui.text_edit_singleline(&mut self.notebook.notes_mut()[i].title);
+                                if title_edit.changed() {
+                                    let note = &mut self.notebook.notes_mut()[i];
+                                    note.touch();
+                                    self.history.record(note.id, Edit::Title { old: old_title, new: note.title.clone() });
+                                }
                                 if title_edit.lost_focus() && ctx.input().key_pressed(eframe::egui::Key::Enter) {
-                                    save_file("data", &mut self.note_warp.notes);
+                                    let id = self.notebook.notes()[i].id;
+                                    self.persist_note(id);
                                 }
                             });
                             // adds partially the content for displa
-                            let mut text = self.note_warp.notes[i].text.clone();
-                            text.retain(|c| c != '\n');
-                            let content = format!("{}...", text.char_range(0..80));
+                            let content = format!("{}...", self.notebook.notes()[i].preview(80));
+                            ui.horizontal(|ui| {
+                                ui.label(RichText::new(local_time(&self.notebook.notes()[i].modified)).small().weak());
+                                if self.notebook.is_dirty(self.notebook.notes()[i].id) {
+                                    ui.label(RichText::new("●").small().color(Color32::from_rgb(230, 160, 60)))
+                                        .on_hover_text("unsaved changes");
+                                }
+                                if self.note_warp.conflicts.contains_key(&self.notebook.notes()[i].id) {
+                                    ui.label(RichText::new("⚠").small().color(Color32::from_rgb(230, 160, 60)))
+                                        .on_hover_text("changed outside of the app, open it to decide what to keep");
+                                }
+                                if ui.small_button("🗑").on_hover_text("Move to trash").clicked() {
+                                    deleted = Some(self.notebook.notes()[i].id);
+                                }
+                            });
                             // if clicking on this, opens up a pop-up for editing the note
                             let note_btn = ui.selectable_label(false, RichText::new(content).size(13.));
                             if note_btn.clicked() {
-                                self.note_warp.bool = true;
-                                self.note = Some(i)
+                                self.note_warp.editor_open = true;
+                                self.note = Some(self.notebook.notes()[i].id)
                             }
                             ui.add_space(5.);
                         });
@@ -277,6 +1050,10 @@ impl SnowApp {
                         scroll_note.response
                             .on_hover_text(RichText::new("A Note!"));
                     }
+
+                    if let Some(id) = deleted {
+                        self.delete_note(id);
+                    }
                 });
             });
         }
@@ -304,20 +1081,70 @@ impl SnowApp {
                             .strong()
                             .heading()
                             .size(15.)));
-                    // TODO: random id
                     if add_note_btn.clicked() {
-                        self.note_warp.bool = true;
-                        let new_note = Note::new(
-                            rand::random::<i32>(),
-                            "".to_string(),
-                            "".to_string(),
-                            [0, 0, 0]);
-                        self.note_warp.notes.push(new_note);
-                        self.note = Some(self.note_warp.notes.len() - 1);
+                        self.note_warp.editor_open = true;
+                        self.note = Some(self.notebook.create().id);
+                    }
+                });
+
+            });
+    }
+
+    // lists the notes in the trash, they can be restored or deleted for good from here
+    fn trash_window(&mut self, ctx: &Context) {
+        let mut open = self.trash_window;
+        let mut restore = None;
+        let mut purge = Vec::new();
+
+        Window::new("Trash")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.set_min_width(250.);
+                ui.label(RichText::new(format!(
+                    "notes are deleted for good after {} days", self.config.trash_retention_days)).small().weak());
+                ui.separator();
+
+                if self.notebook.trashed().next().is_none() {
+                    ui.label("the trash is empty");
+                    return;
+                }
+
+                ScrollArea::vertical().max_height(300.).show(ui, |ui| {
+                    for note in self.notebook.trashed() {
+                        ui.horizontal(|ui| {
+                            ui.vertical(|ui| {
+                                ui.label(RichText::new(if note.title.is_empty() { "untitled" } else { &note.title }).strong());
+                                if let Some(deleted) = &note.deleted {
+                                    ui.label(RichText::new(format!("deleted {}", local_time(deleted))).small().weak());
+                                }
+                            });
+                            ui.with_layout(Layout::right_to_left(), |ui| {
+                                if ui.button("Delete forever").clicked() {
+                                    purge.push(note.id);
+                                }
+                                if ui.button("Restore").clicked() {
+                                    restore = Some(note.id);
+                                }
+                            });
+                        });
+                        ui.separator();
                     }
                 });
 
+                if ui.button("Empty trash").clicked() {
+                    purge.extend(self.notebook.trashed().map(|note| note.id));
+                }
             });
+
+        self.trash_window = open;
+        if let Some(id) = restore {
+            self.restore_note(id);
+        }
+        for id in purge {
+            self.purge_note(id);
+        }
     }
 
     pub fn config_window(&mut self, ctx: &Context) {
@@ -329,7 +1156,6 @@ impl SnowApp {
         let response = window.show(ctx, |ui| {
 
             ui.set_max_width(200.);
-            ui.set_max_height(100.);
 
             egui::menu::bar(ui, |ui| {
                 ui.with_layout(Layout::left_to_right(), |ui| {
@@ -349,17 +1175,328 @@ impl SnowApp {
             });
             egui::CentralPanel::default()
                 .show_inside(ui, |ui| {
-                    ui.set_max_height(50.);
                     ui.set_max_width(80.);
+                    self.render_sync_settings(ui);
+                    ui.add_space(5.);
+
+                    // storage backend selection
+                    ui.label(RichText::new("Storage").strong());
+                    let mut backend = self.config.backend.clone();
+                    ui.radio_value(&mut backend, StorageBackend::Json, "JSON file");
+                    ui.radio_value(&mut backend, StorageBackend::Sqlite, "SQLite");
+                    let vault = matches!(backend, StorageBackend::Vault(_));
+                    if ui.radio(vault, "Markdown vault").clicked() && !vault {
+                        backend = StorageBackend::Vault(PathBuf::from(&self.vault_dir));
+                    }
+                    // the vault directory can be changed while the vault is in use as well
                     ui.horizontal(|ui| {
                         ui.add_space(10.);
-                        let drive_btn = ui.add(Button::new("Google Drive"));
+                        ui.add(TextEdit::singleline(&mut self.vault_dir).desired_width(120.))
+                            .on_hover_text("Directory of the markdown vault");
+                        if vault && ui.button("Move").clicked() {
+                            backend = StorageBackend::Vault(PathBuf::from(&self.vault_dir));
+                        }
                     });
+                    if backend != self.config.backend {
+                        self.switch_backend(backend);
+                    }
+                    ui.add_space(5.);
+
+                    ui.label(RichText::new("Data directory").strong());
+                    let movable = self.location.source.is_configurable() && !self.read_only;
+                    ui.horizontal(|ui| {
+                        ui.add(TextEdit::singleline(&mut self.data_dir).desired_width(120.))
+                            .on_hover_text(format!("Currently '{}'", self.location.data_dir.display()));
+                        let move_btn = ui.add_enabled(movable, Button::new("Move my data"))
+                            .on_disabled_hover_text(if self.read_only {
+                                "Another copy of the app is using the notes"
+                            } else {
+                                "Set with --data-dir or SNOW_TREADING_DATA, change it there"
+                            });
+                        if move_btn.clicked() && PathBuf::from(&self.data_dir) != self.location.data_dir {
+                            self.move_data(PathBuf::from(&self.data_dir));
+                        }
+                    });
+                    if self.location.portable {
+                        ui.label(RichText::new("portable mode").small());
+                    }
+                    ui.add_space(5.);
+
+                    ui.label(RichText::new("Trash").strong());
+                    ui.horizontal(|ui| {
+                        ui.label("keep for");
+                        let retention = ui.add(egui::DragValue::new(&mut self.config.trash_retention_days)
+                            .clamp_range(1..=365)
+                            .suffix(" days"));
+                        if retention.changed() {
+                            self.store_confy();
+                        }
+                    });
+                    ui.add_space(5.);
+
+                    ui.label(RichText::new("Autosave").strong());
+                    ui.horizontal(|ui| {
+                        let enabled = ui.checkbox(&mut self.config.autosave, "after");
+                        let delay = ui.add_enabled(self.config.autosave, egui::DragValue::new(&mut self.config.autosave_delay)
+                            .clamp_range(0.5..=60.)
+                            .speed(0.1)
+                            .suffix(" s"))
+                            .on_hover_text("How long to wait after the last change");
+                        ui.label("without changes");
+                        if enabled.changed() {
+                            self.autosave.set_enabled(self.config.autosave);
+                        }
+                        if delay.changed() {
+                            self.autosave.set_delay(Duration::from_secs_f32(self.config.autosave_delay));
+                        }
+                        if enabled.changed() || delay.changed() {
+                            self.store_confy();
+                        }
+                    });
+                    ui.add_space(5.);
+
+                    ui.label(RichText::new("History").strong());
+                    ui.horizontal(|ui| {
+                        ui.label("keep");
+                        let limit = ui.add(egui::DragValue::new(&mut self.config.revision_limit)
+                            .clamp_range(0..=1000)
+                            .suffix(" revisions"))
+                            .on_hover_text("0 keeps every revision");
+                        if limit.changed() {
+                            self.store_confy();
+                        }
+                    });
+                    ui.add_space(5.);
+
+                    // restore picker for the rotating backups of the json store
+                    if self.config.backend == StorageBackend::Json {
+                        ui.label(RichText::new("Restore backup").strong());
+                        match storage::list_backups("data") {
+                            Ok(backups) if backups.is_empty() => {
+                                ui.label("no backups yet");
+                            }
+                            Ok(backups) => {
+                                for backup in backups {
+                                    ui.horizontal(|ui| {
+                                        ui.label(backup.created.format("%d.%m.%Y %H:%M:%S").to_string());
+                                        if ui.button("Restore").clicked() {
+                                            self.restore_backup(&backup);
+                                        }
+                                    });
+                                }
+                            }
+                            Err(e) => {
+                                ui.label(format!("could not list backups: {}", e));
+                            }
+                        }
+                    }
                 });
 
         });
     }
 
+    fn switch_backend(&mut self, backend: StorageBackend) {
+        // unsaved edits go to the old backend first, a backend with notes replaces the notebook
+        if self.recovery.is_none() && !self.read_only {
+            for id in self.notebook.dirty() {
+                if !self.note_warp.conflicts.contains_key(&id) {
+                    self.persist_note(id);
+                }
+            }
+        }
+        // pending writes still belong to the old backend
+        self.flush();
+        // an empty backend gets the current notes, one that already has notes is opened as is
+        let result = storage::open(&backend).and_then(|mut store| {
+            let existing = store.load_all()?;
+            if existing.is_empty() {
+                store.save_all(self.notebook.notes())?;
+            }
+            Ok((store, existing))
+        });
+        match result {
+            Ok((_, existing)) if !existing.is_empty() && !self.notebook.dirty().is_empty() => {
+                warn!("[Snow]: not switching storage backend, {} note(s) are not saved", self.notebook.dirty().len());
+                self.notifier.warning("save or discard your changes before switching storage");
+            }
+            Ok((store, existing)) => {
+                info!("[Snow]: switched storage backend to {:?}", backend);
+                self.notifier.success("storage switched");
+                if !existing.is_empty() {
+                    self.notebook = Notebook::new(existing);
+                    self.history.clear();
+                    self.note_warp.editor_open = false;
+                    self.note = None;
+                } else {
+                    self.notebook.mark_all_saved();
+                }
+                // swapped inside the lock, the worker shares it
+                *lock(&self.store) = store;
+                self.watcher = watch(&self.store, &self.notifier);
+                self.note_warp.conflicts.clear();
+                self.config.backend = backend;
+                self.store_confy();
+            }
+            Err(e) => {
+                error!("[Snow]: could not switch storage backend: {}", e);
+                self.notifier.error(format!("could not switch storage: {}", e));
+            }
+        }
+    }
+
+    fn render_sync_settings(&mut self, ui: &mut Ui) {
+        ui.label(RichText::new("Sync").strong());
+        if let Some(syncer) = &self.syncer {
+            ui.label(RichText::new(syncer.name()).small());
+            let turn_off = ui.horizontal(|ui| {
+                if ui.button("Sync now").clicked() {
+                    syncer.sync_now();
+                }
+                ui.button("Turn off").clicked()
+            }).inner;
+            if turn_off {
+                self.disconnect_sync();
+            }
+            return;
+        }
+        if self.login.is_some() {
+            ui.label("waiting for the login in your browser...");
+            return;
+        }
+
+        let read_only = self.read_only;
+        let mut connect = None;
+        ui.collapsing("Google Drive", |ui| {
+            ui.add(TextEdit::singleline(&mut self.google.client_id).hint_text("client id").desired_width(120.))
+                .on_hover_text("OAuth client of a Google cloud project (\"Desktop app\")");
+            ui.add(TextEdit::singleline(&mut self.google.client_secret).hint_text("client secret").password(true).desired_width(120.));
+            let ready = !self.google.client_id.is_empty() && !read_only;
+            if ui.add_enabled(ready, Button::new("Log in")).clicked() {
+                connect = Some(CloudProvider::GoogleDrive(self.google.clone()));
+            }
+        });
+        ui.collapsing("WebDAV", |ui| {
+            ui.add(TextEdit::singleline(&mut self.webdav.url).hint_text("https://...").desired_width(120.))
+                .on_hover_text("the folder on the WebDAV server the notes go into");
+            ui.add(TextEdit::singleline(&mut self.webdav.user).hint_text("user").desired_width(120.));
+            ui.add(TextEdit::singleline(&mut self.webdav.password).hint_text("password").password(true).desired_width(120.))
+                .on_hover_text("for Nextcloud create an app password in the security settings");
+            let ready = !self.webdav.url.is_empty() && !read_only;
+            if ui.add_enabled(ready, Button::new("Connect")).clicked() {
+                connect = Some(CloudProvider::WebDav(self.webdav.clone()));
+            }
+        });
+        ui.collapsing("S3 / MinIO", |ui| {
+            ui.add(TextEdit::singleline(&mut self.s3.endpoint).hint_text("https://...").desired_width(120.))
+                .on_hover_text("e.g. https://s3.eu-central-1.amazonaws.com or http://localhost:9000");
+            ui.add(TextEdit::singleline(&mut self.s3.region).hint_text("region").desired_width(120.));
+            ui.add(TextEdit::singleline(&mut self.s3.bucket).hint_text("bucket").desired_width(120.));
+            ui.add(TextEdit::singleline(&mut self.s3.prefix).hint_text("prefix").desired_width(120.))
+                .on_hover_text("the \"folder\" inside the bucket the notes go into");
+            ui.add(TextEdit::singleline(&mut self.s3.access_key).hint_text("access key").desired_width(120.));
+            ui.add(TextEdit::singleline(&mut self.s3.secret_key).hint_text("secret key").password(true).desired_width(120.));
+            let ready = !self.s3.endpoint.is_empty() && !self.s3.bucket.is_empty() && !read_only;
+            if ui.add_enabled(ready, Button::new("Connect")).clicked() {
+                connect = Some(CloudProvider::S3(self.s3.clone()));
+            }
+        });
+        match connect {
+            Some(CloudProvider::GoogleDrive(_)) => self.connect_google(),
+            Some(provider) => self.connect(provider),
+            None => {}
+        }
+    }
+
+    // copies everything in the data dir to `to`, uses it from now on and only then
+    // removes the old files, so a failure anywhere leaves the data where it was
+    fn move_data(&mut self, to: PathBuf) {
+        // pending writes still belong to the old directory
+        self.flush();
+        let from = self.location.data_dir.clone();
+        let files = match storage::copy_data(&from, &to) {
+            Ok(files) => files,
+            Err(e) => {
+                error!("[Snow]: could not move the data to '{}': {}", to.display(), e);
+                self.notifier.error(format!("could not move data: {}", e));
+                return;
+            }
+        };
+
+        // a vault inside the data dir moves along with it
+        let backend = match &self.config.backend {
+            StorageBackend::Vault(dir) => match dir.strip_prefix(&from) {
+                Ok(inside) => StorageBackend::Vault(to.join(inside)),
+                Err(_) => StorageBackend::Vault(dir.clone()),
+            },
+            backend => backend.clone(),
+        };
+        storage::set_data_dir(&to);
+        let opened = storage::open(&backend).and_then(|mut store| {
+            store.load_all()?;
+            Ok(store)
+        });
+        let store = match opened {
+            Ok(store) => store,
+            Err(e) => {
+                error!("[Snow]: could not open the notes in '{}': {}", to.display(), e);
+                self.notifier.error(format!("could not move data: {}", e));
+                storage::set_data_dir(&from);
+                let _ = storage::remove_data(&to, &files);
+                return;
+            }
+        };
+
+        *lock(&self.store) = store;
+        if let StorageBackend::Vault(dir) = &backend {
+            self.vault_dir = dir.display().to_string();
+        }
+        self.config.backend = backend;
+        self.config.data_dir = Some(to.clone());
+        self.location.data_dir = to.clone();
+        self.location.source = Source::Config;
+        self.store_confy();
+        self.watcher = watch(&self.store, &self.notifier);
+        // the sync state and login moved as well
+        if self.syncer.is_some() {
+            self.restart_sync();
+        }
+
+        // the lock moves along, so the next launch still finds us
+        self._instance = None;
+        if let Ok(Instance::Primary(mut instance)) = Instance::acquire() {
+            self.shown = instance.listen(self.notifier.clone());
+            self._instance = Some(instance);
+        }
+
+        if let Err(e) = storage::remove_data(&from, &files) {
+            warn!("[Snow]: could not remove the old data in '{}': {}", from.display(), e);
+            self.notifier.warning(format!("the old files in '{}' were kept", from.display()));
+        }
+        info!("[Snow]: moved the data from '{}' to '{}'", from.display(), to.display());
+        self.notifier.success("data moved!");
+    }
+
+    fn restore_backup(&mut self, backup: &storage::Backup) {
+        // a write landing after the restore would undo part of it
+        self.flush();
+        let result = storage::restore_backup("data", backup).and_then(|_| lock(&self.store).load_all());
+        match result {
+            Ok(notes) => {
+                self.notebook = Notebook::new(notes);
+                self.history.clear();
+                self.note_warp.conflicts.clear();
+                // the open note might not exist in the restored state
+                self.note_warp.editor_open = false;
+                self.note = None;
+                self.notifier.success("backup restored!");
+            }
+            Err(e) => {
+                error!("[Snow]: could not restore backup: {}", e);
+                self.notifier.error(format!("could not restore backup: {}", e));
+            }
+        }
+    }
+
     fn configure_fonts(&self, ctx: &Context) {
         // create font def object
         let mut font_def = FontDefinitions::default();
@@ -387,9 +1524,8 @@ impl SnowApp {
 
     /// Simple convenience function for quickly saving the app state.
     pub fn store_confy(&mut self) {
-        confy::store(self.name(), AppConfig {
-            dark_mode: self.config.dark_mode,
-            bookmark_panel: self.config.bookmark_panel
-        });
+        if let Err(e) = store_config(&self.location.config_file, &self.config) {
+            error!("[Snow]: could not store config: {}", e);
+        }
     }
 }
@@ -1,2 +1,48 @@
-use reqwest;
+use super::CloudError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 
+/// A file as seen by a [`CloudStorage`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemoteMeta {
+    pub name: String,
+    /// changes whenever the contents change, e.g. an etag or a content hash
+    pub version: String,
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// What a write expects to find, so it does not overwrite a change it has not seen.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expect {
+    /// writes no matter what is there
+    Any,
+    /// only creates the file, fails if it exists already
+    Missing,
+    /// only replaces the file in exactly this version
+    Version(String),
+}
+
+/// A flat folder of files somewhere else, which notes can be synced to with a
+/// [`SyncEngine`](super::SyncEngine). Writes that find something other than they
+/// [`Expect`] fail with [`CloudError::Conflict`].
+#[async_trait]
+pub trait CloudStorage: Send + Sync {
+    /// Where the files go, e.g. the url of the folder. The sync state is only reused as
+    /// long as this stays the same.
+    fn name(&self) -> String;
+
+    async fn list(&self) -> Result<Vec<RemoteMeta>, CloudError>;
+
+    /// `None` if there is no such file.
+    async fn metadata(&self, name: &str) -> Result<Option<RemoteMeta>, CloudError>;
+
+    /// The contents of the file and the version they belong to, `None` if there is no such file.
+    async fn get(&self, name: &str) -> Result<Option<(RemoteMeta, Vec<u8>)>, CloudError>;
+
+    /// Creates or replaces the file. Returns what it looks like afterwards.
+    async fn put(&self, name: &str, data: Vec<u8>, expect: Expect) -> Result<RemoteMeta, CloudError>;
+
+    /// Returns whether there was such a file.
+    async fn delete(&self, name: &str, expect: Expect) -> Result<bool, CloudError>;
+}
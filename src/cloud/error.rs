@@ -0,0 +1,56 @@
+use crate::StorageError;
+use std::fmt;
+
+/// Everything that can go wrong while talking to a [`CloudStorage`](super::CloudStorage)
+/// or syncing with it.
+#[derive(Debug)]
+pub enum CloudError {
+    /// the server could not be reached or the connection broke
+    Network(Box<dyn std::error::Error + Send + Sync>),
+    /// not logged in, or the credentials were rejected
+    Auth(String),
+    /// the file changed since it was last seen, see [`Expect`](super::Expect)
+    Conflict(String),
+    /// the server answered with an unexpected status
+    Status { status: u16, message: String },
+    /// the server answered, but not with anything we understand
+    Invalid(String),
+    /// reading or writing the local side failed
+    Storage(StorageError),
+}
+
+impl CloudError {
+    /// Whether trying the next file is pointless, e.g. because the server is unreachable.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, CloudError::Network(_) | CloudError::Auth(_))
+    }
+}
+
+impl From<StorageError> for CloudError {
+    fn from(e: StorageError) -> Self {
+        CloudError::Storage(e)
+    }
+}
+
+impl fmt::Display for CloudError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloudError::Network(source) => write!(f, "network error: {}", source),
+            CloudError::Auth(message) => write!(f, "not authorized: {}", message),
+            CloudError::Conflict(name) => write!(f, "'{}' was changed by someone else", name),
+            CloudError::Status { status, message } => write!(f, "server answered {}: {}", status, message),
+            CloudError::Invalid(message) => write!(f, "unexpected answer: {}", message),
+            CloudError::Storage(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CloudError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CloudError::Network(source) => Some(source.as_ref()),
+            CloudError::Storage(e) => Some(e),
+            _ => None,
+        }
+    }
+}
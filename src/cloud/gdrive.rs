@@ -0,0 +1,732 @@
+use super::http::check;
+use super::{CloudError, CloudStorage, Expect, RemoteMeta};
+use crate::storage::{data_dir, read_json, write_private};
+use crate::StorageError;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+
+// only files the app created itself can be seen
+const SCOPE: &str = "https://www.googleapis.com/auth/drive.file";
+const FOLDER_MIME: &str = "application/vnd.google-apps.folder";
+const FILE_FIELDS: &str = "id,name,version,size,modifiedTime";
+// the access token is refreshed a bit before it runs out
+const EXPIRY_MARGIN_SECS: i64 = 60;
+// how long the browser login may take
+const LOGIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+const TOKEN_FILE: &str = "gdrive-token.json";
+
+/// Where the requests go, the Google urls unless they are pointed at e.g. a local mock server.
+#[derive(Clone, Debug)]
+pub struct Endpoints {
+    pub auth: String,
+    pub token: String,
+    pub api: String,
+    pub upload: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            auth: "https://accounts.google.com/o/oauth2/v2/auth".to_owned(),
+            token: "https://oauth2.googleapis.com/token".to_owned(),
+            api: "https://www.googleapis.com/drive/v3".to_owned(),
+            upload: "https://www.googleapis.com/upload/drive/v3".to_owned(),
+        }
+    }
+}
+
+/// The OAuth client of the app, as registered in the Google cloud console ("Desktop app").
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GoogleCredentials {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Token {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_at: DateTime<Utc>,
+    // the email address of the account, missing in tokens of older builds
+    #[serde(default)]
+    account: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DriveFile {
+    id: String,
+    name: String,
+    version: Option<String>,
+    size: Option<String>,
+    modified_time: Option<DateTime<Utc>>,
+}
+
+impl DriveFile {
+    fn meta(&self) -> RemoteMeta {
+        RemoteMeta {
+            name: self.name.clone(),
+            version: self.version.clone().unwrap_or_default(),
+            size: self.size.as_deref().and_then(|size| size.parse().ok()).unwrap_or(0),
+            modified: self.modified_time,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct About {
+    user: AboutUser,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AboutUser {
+    email_address: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileList {
+    files: Vec<DriveFile>,
+    next_page_token: Option<String>,
+}
+
+/// Syncs notes into a folder of the user's Google Drive. Logging in happens in the browser
+/// (see [`GoogleDrive::login`]), the token is kept in the data dir and refreshed as needed.
+pub struct GoogleDrive {
+    client: Client,
+    endpoints: Endpoints,
+    credentials: GoogleCredentials,
+    folder_name: String,
+    token_file: PathBuf,
+    // who is logged in, as of the token the drive was created with
+    account: Option<String>,
+    token: Mutex<Option<Token>>,
+    // the id of the folder and of every file in it, by name
+    folder: Mutex<Option<String>>,
+    ids: Mutex<HashMap<String, String>>,
+}
+
+impl GoogleDrive {
+    /// Picks up the token of an earlier login, if there is one.
+    pub fn new(credentials: GoogleCredentials, endpoints: Endpoints) -> Result<Self, StorageError> {
+        GoogleDrive::with_token_file(credentials, endpoints, data_dir()?.join(TOKEN_FILE))
+    }
+
+    // like `new`, but the token is kept in `token_file` instead of the data dir
+    pub(crate) fn with_token_file(credentials: GoogleCredentials, endpoints: Endpoints, token_file: PathBuf) -> Result<Self, StorageError> {
+        let token = match read_json::<Token>(&token_file) {
+            Ok(token) => Some(token),
+            Err(StorageError::Missing(_)) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(GoogleDrive {
+            client: Client::new(),
+            endpoints,
+            credentials,
+            folder_name: "Snow Treading".to_owned(),
+            token_file,
+            account: token.as_ref().and_then(|token| token.account.clone()),
+            token: Mutex::new(token),
+            folder: Mutex::new(None),
+            ids: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn is_logged_in(&self) -> bool {
+        // only locked while a request is being made, which needs a token
+        self.token.try_lock().map_or(true, |token| token.is_some())
+    }
+
+    /// Logs in with the installed app flow: `open` is handed the url of the Google login page
+    /// (e.g. to open it in the browser), which redirects back to a port on this machine.
+    pub async fn login(&self, open: impl FnOnce(&str)) -> Result<(), CloudError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.map_err(network)?;
+        let redirect = format!("http://127.0.0.1:{}", listener.local_addr().map_err(network)?.port());
+
+        // PKCE, so a code that is intercepted on the way back is useless to anyone else
+        let verifier = random(64);
+        let challenge = base64::encode_config(Sha256::digest(verifier.as_bytes()), base64::URL_SAFE_NO_PAD);
+        let state = random(16);
+        let url = Url::parse_with_params(&self.endpoints.auth, &[
+            ("client_id", self.credentials.client_id.as_str()),
+            ("redirect_uri", redirect.as_str()),
+            ("response_type", "code"),
+            ("scope", SCOPE),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", "S256"),
+            ("state", state.as_str()),
+            ("access_type", "offline"),
+            ("prompt", "consent"),
+        ]).map_err(|e| CloudError::Invalid(e.to_string()))?;
+        open(url.as_str());
+
+        let code = tokio::time::timeout(LOGIN_TIMEOUT, receive_code(&listener, &state)).await
+            .map_err(|_| CloudError::Auth("the login was not finished in time".to_owned()))??;
+        let response = self.client.post(&self.endpoints.token)
+            .form(&[
+                ("code", code.as_str()),
+                ("client_id", self.credentials.client_id.as_str()),
+                ("client_secret", self.credentials.client_secret.as_str()),
+                ("redirect_uri", redirect.as_str()),
+                ("grant_type", "authorization_code"),
+                ("code_verifier", verifier.as_str()),
+            ])
+            .send().await?;
+        let response: TokenResponse = check(response, "token").await?.json().await?;
+        let access = response.access_token.clone();
+        let account = self.account(&access).await?;
+        self.store_token(response, None, Some(account.clone())).await?;
+        info!("[Snow]: logged in to Google Drive as {}", account);
+        Ok(())
+    }
+
+    // the email address of whoever the token belongs to
+    async fn account(&self, access: &str) -> Result<String, CloudError> {
+        let response = self.client.get(format!("{}/about", self.endpoints.api))
+            .bearer_auth(access)
+            .query(&[("fields", "user(emailAddress)")])
+            .send().await?;
+        let about: About = check(response, "about").await?.json().await?;
+        Ok(about.user.email_address)
+    }
+
+    /// Forgets the token, the next sync needs a new login.
+    pub async fn logout(&self) -> Result<(), StorageError> {
+        *self.token.lock().await = None;
+        match std::fs::remove_file(&self.token_file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(StorageError::writing(&self.token_file, e)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn store_token(&self, response: TokenResponse, refresh_token: Option<String>, account: Option<String>) -> Result<String, CloudError> {
+        let token = Token {
+            access_token: response.access_token,
+            // google only hands out a refresh token on the first login
+            refresh_token: response.refresh_token.or(refresh_token),
+            expires_at: Utc::now() + Duration::seconds(response.expires_in),
+            account,
+        };
+        // the token gives access to the user's drive, nobody else should read it
+        let contents = serde_json::to_vec_pretty(&token).map_err(|e| CloudError::Invalid(e.to_string()))?;
+        write_private(&self.token_file, &contents)?;
+        let access = token.access_token.clone();
+        *self.token.lock().await = Some(token);
+        Ok(access)
+    }
+
+    // a valid access token, refreshed first if it ran out
+    async fn access_token(&self) -> Result<String, CloudError> {
+        let token = self.token.lock().await.clone()
+            .ok_or_else(|| CloudError::Auth("not logged in to Google Drive".to_owned()))?;
+        if token.expires_at - Duration::seconds(EXPIRY_MARGIN_SECS) > Utc::now() {
+            return Ok(token.access_token);
+        }
+        let refresh = token.refresh_token
+            .ok_or_else(|| CloudError::Auth("the Google Drive login ran out".to_owned()))?;
+        let response = self.client.post(&self.endpoints.token)
+            .form(&[
+                ("refresh_token", refresh.as_str()),
+                ("client_id", self.credentials.client_id.as_str()),
+                ("client_secret", self.credentials.client_secret.as_str()),
+                ("grant_type", "refresh_token"),
+            ])
+            .send().await?;
+        let response: TokenResponse = match check(response, "token").await {
+            Ok(response) => response.json().await?,
+            // the refresh token was revoked, e.g. in the google account settings
+            Err(CloudError::Status { status: 400, message }) => return Err(CloudError::Auth(message)),
+            Err(e) => return Err(e),
+        };
+        debug!("refreshed the Google Drive token");
+        self.store_token(response, Some(refresh), token.account).await
+    }
+
+    // the id of the app folder, created on first use
+    async fn folder(&self) -> Result<String, CloudError> {
+        let mut folder = self.folder.lock().await;
+        if let Some(id) = &*folder {
+            return Ok(id.clone());
+        }
+        let query = format!("name = '{}' and mimeType = '{}' and trashed = false", self.folder_name, FOLDER_MIME);
+        let found = self.query(&query).await?.into_iter().next();
+        let id = match found {
+            Some(file) => file.id,
+            None => {
+                let response = self.client.post(format!("{}/files", self.endpoints.api))
+                    .bearer_auth(self.access_token().await?)
+                    .query(&[("fields", "id,name")])
+                    .json(&serde_json::json!({ "name": self.folder_name, "mimeType": FOLDER_MIME }))
+                    .send().await?;
+                let file: DriveFile = check(response, &self.folder_name).await?.json().await?;
+                info!("[Snow]: created the folder '{}' in Google Drive", self.folder_name);
+                file.id
+            }
+        };
+        *folder = Some(id.clone());
+        Ok(id)
+    }
+
+    // every file matching `query`, across all pages
+    async fn query(&self, query: &str) -> Result<Vec<DriveFile>, CloudError> {
+        let mut files = Vec::new();
+        let mut page = None;
+        loop {
+            let mut request = self.client.get(format!("{}/files", self.endpoints.api))
+                .bearer_auth(self.access_token().await?)
+                .query(&[("q", query), ("fields", &format!("nextPageToken,files({})", FILE_FIELDS)), ("pageSize", "1000")]);
+            if let Some(page) = &page {
+                request = request.query(&[("pageToken", page)]);
+            }
+            let list: FileList = check(request.send().await?, "files").await?.json().await?;
+            files.extend(list.files);
+            match list.next_page_token {
+                Some(next) => page = Some(next),
+                None => return Ok(files),
+            }
+        }
+    }
+
+    // looks up a single file, the id is remembered for later requests
+    async fn find(&self, name: &str) -> Result<Option<DriveFile>, CloudError> {
+        let id = self.ids.lock().await.get(name).cloned();
+        if let Some(id) = id {
+            let response = self.client.get(format!("{}/files/{}", self.endpoints.api, id))
+                .bearer_auth(self.access_token().await?)
+                .query(&[("fields", &format!("{},trashed", FILE_FIELDS))])
+                .send().await?;
+            if response.status() != reqwest::StatusCode::NOT_FOUND {
+                let file: serde_json::Value = check(response, name).await?.json().await?;
+                if file["trashed"] != serde_json::Value::Bool(true) {
+                    return serde_json::from_value(file).map(Some).map_err(|e| CloudError::Invalid(e.to_string()));
+                }
+            }
+            self.ids.lock().await.remove(name);
+        }
+
+        let query = format!("name = '{}' and '{}' in parents and trashed = false", name.replace('\'', "\\'"), self.folder().await?);
+        let file = self.query(&query).await?.into_iter().next();
+        if let Some(file) = &file {
+            self.ids.lock().await.insert(name.to_owned(), file.id.clone());
+        }
+        Ok(file)
+    }
+
+    // fails unless the file is in the state the caller expects
+    async fn expect(&self, name: &str, expect: &Expect) -> Result<Option<DriveFile>, CloudError> {
+        let current = self.find(name).await?;
+        let expected = match (expect, &current) {
+            (Expect::Any, _) | (Expect::Missing, None) => true,
+            (Expect::Version(version), Some(current)) => current.version.as_deref() == Some(version.as_str()),
+            _ => false,
+        };
+        if !expected {
+            return Err(CloudError::Conflict(name.to_owned()));
+        }
+        Ok(current)
+    }
+}
+
+#[async_trait]
+impl CloudStorage for GoogleDrive {
+    // another account has other files, what was synced with one says nothing about the other
+    fn name(&self) -> String {
+        match &self.account {
+            Some(account) => format!("gdrive:{}:{}", account, self.folder_name),
+            None => format!("gdrive:{}", self.folder_name),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<RemoteMeta>, CloudError> {
+        let query = format!("'{}' in parents and trashed = false", self.folder().await?);
+        let files = self.query(&query).await?;
+        let mut ids = self.ids.lock().await;
+        ids.clear();
+        ids.extend(files.iter().map(|file| (file.name.clone(), file.id.clone())));
+        Ok(files.iter().map(DriveFile::meta).collect())
+    }
+
+    async fn metadata(&self, name: &str) -> Result<Option<RemoteMeta>, CloudError> {
+        Ok(self.find(name).await?.map(|file| file.meta()))
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<(RemoteMeta, Vec<u8>)>, CloudError> {
+        let file = match self.find(name).await? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let response = self.client.get(format!("{}/files/{}", self.endpoints.api, file.id))
+            .bearer_auth(self.access_token().await?)
+            .query(&[("alt", "media")])
+            .send().await?;
+        let data = check(response, name).await?.bytes().await?;
+        Ok(Some((file.meta(), data.to_vec())))
+    }
+
+    // drive can not check a version while writing, so it is checked right before
+    async fn put(&self, name: &str, data: Vec<u8>, expect: Expect) -> Result<RemoteMeta, CloudError> {
+        let request = match self.expect(name, &expect).await? {
+            Some(file) => self.client.patch(format!("{}/files/{}", self.endpoints.upload, file.id))
+                .query(&[("uploadType", "media"), ("fields", FILE_FIELDS)])
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(data),
+            None => {
+                let boundary = format!("snow-{}", random(16));
+                let metadata = serde_json::json!({ "name": name, "parents": [self.folder().await?] });
+                let mut body = format!(
+                    "--{b}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{}\r\n--{b}\r\nContent-Type: application/json\r\n\r\n",
+                    metadata, b = boundary,
+                ).into_bytes();
+                body.extend_from_slice(&data);
+                body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+                self.client.post(format!("{}/files", self.endpoints.upload))
+                    .query(&[("uploadType", "multipart"), ("fields", FILE_FIELDS)])
+                    .header(reqwest::header::CONTENT_TYPE, format!("multipart/related; boundary={}", boundary))
+                    .body(body)
+            }
+        };
+        let response = request.bearer_auth(self.access_token().await?).send().await?;
+        let file: DriveFile = check(response, name).await?.json().await?;
+        self.ids.lock().await.insert(name.to_owned(), file.id.clone());
+        Ok(file.meta())
+    }
+
+    async fn delete(&self, name: &str, expect: Expect) -> Result<bool, CloudError> {
+        let file = match self.expect(name, &expect).await? {
+            Some(file) => file,
+            None => return Ok(false),
+        };
+        let response = self.client.delete(format!("{}/files/{}", self.endpoints.api, file.id))
+            .bearer_auth(self.access_token().await?)
+            .send().await?;
+        check(response, name).await?;
+        self.ids.lock().await.remove(name);
+        Ok(true)
+    }
+}
+
+// waits for the browser to come back from the login page, answers it and returns the code
+async fn receive_code(listener: &TcpListener, state: &str) -> Result<String, CloudError> {
+    loop {
+        let (mut stream, _) = listener.accept().await.map_err(network)?;
+        let mut request = vec![0; 8192];
+        let read = stream.read(&mut request).await.map_err(network)?;
+        let request = String::from_utf8_lossy(&request[..read]);
+        // "GET /?code=...&state=... HTTP/1.1"
+        let path = request.split_whitespace().nth(1).unwrap_or("/");
+        let url = match Url::parse(&format!("http://127.0.0.1{}", path)) {
+            Ok(url) => url,
+            Err(_) => continue,
+        };
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        // e.g. the browser asking for a favicon
+        if !params.contains_key("code") && !params.contains_key("error") {
+            respond(&mut stream, "Nothing to see here.").await;
+            continue;
+        }
+        if params.get("state").map(String::as_str) != Some(state) {
+            respond(&mut stream, "This login did not come from Snow Treading.").await;
+            return Err(CloudError::Auth("the login answer did not match the request".to_owned()));
+        }
+        if let Some(error) = params.get("error") {
+            respond(&mut stream, "The login was cancelled, you can close this window.").await;
+            return Err(CloudError::Auth(error.clone()));
+        }
+        respond(&mut stream, "Logged in! You can close this window and go back to Snow Treading.").await;
+        return Ok(params["code"].clone());
+    }
+}
+
+async fn respond(stream: &mut tokio::net::TcpStream, message: &str) {
+    let body = format!("<html><body><p>{}</p></body></html>", message);
+    let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+fn random(len: usize) -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(len).map(char::from).collect()
+}
+
+fn network(e: std::io::Error) -> CloudError {
+    CloudError::Network(e.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloud::stub::{json, respond, Received, Stub};
+    use serde_json::json;
+    use std::path::Path;
+
+    // a drive talking to `stub`, keeping its token in `dir`. logged in if there is a `token`
+    fn drive(stub: &Stub, token: Option<Token>, dir: &Path) -> GoogleDrive {
+        let url = stub.url();
+        let endpoints = Endpoints {
+            auth: format!("{}/auth", url),
+            token: format!("{}/token", url),
+            api: format!("{}/drive/v3", url),
+            upload: format!("{}/upload/drive/v3", url),
+        };
+        let credentials = GoogleCredentials { client_id: "client".to_owned(), client_secret: "secret".to_owned() };
+        if let Some(token) = token {
+            write_private(&dir.join(TOKEN_FILE), &serde_json::to_vec(&token).unwrap()).unwrap();
+        }
+        GoogleDrive::with_token_file(credentials, endpoints, dir.join(TOKEN_FILE)).unwrap()
+    }
+
+    fn token(expires_in: i64) -> Token {
+        Token {
+            access_token: "access".to_owned(),
+            refresh_token: Some("refresh".to_owned()),
+            expires_at: Utc::now() + Duration::seconds(expires_in),
+            account: Some("me@example.com".to_owned()),
+        }
+    }
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("snow-treading-gdrive-{}", random(8)));
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn file(id: &str, name: &str, version: &str) -> serde_json::Value {
+        json!({ "id": id, "name": name, "version": version, "size": "2", "modifiedTime": "2022-03-01T09:30:00Z" })
+    }
+
+    // the folder exists already, everything else is up to `files`
+    fn drive_api(mut files: impl FnMut(&Received) -> Option<hyper::Response<hyper::Body>> + Send + 'static) -> Stub {
+        Stub::start(move |request| {
+            if let Some(response) = files(request) {
+                return response;
+            }
+            match request.param("q") {
+                Some(query) if query.contains(FOLDER_MIME) => json(json!({ "files": [file("folder", "Snow Treading", "1")] })),
+                _ => respond(404, "not found"),
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn refreshes_a_token_that_ran_out() {
+        let dir = TempDir::new();
+        let stub = drive_api(|request| match request.path.as_str() {
+            "/token" => Some(json(json!({ "access_token": "fresh", "expires_in": 3600 }))),
+            "/drive/v3/files" if request.param("q").is_some_and(|q| q.contains("'folder' in parents")) => Some(json(json!({ "files": [] }))),
+            _ => None,
+        });
+        let drive = drive(&stub, Some(token(-10)), &dir.0);
+
+        assert!(drive.list().await.unwrap().is_empty());
+        let received = stub.received();
+        assert_eq!(received[0].path, "/token");
+        assert_eq!(received[0].param("grant_type").as_deref(), Some("refresh_token"));
+        assert_eq!(received[0].param("refresh_token").as_deref(), Some("refresh"));
+        assert!(received[1..].iter().all(|request| request.header("authorization") == Some("Bearer fresh")));
+
+        // the refresh token and account are kept, only for this user to read
+        let stored: Token = read_json(&dir.0.join(TOKEN_FILE)).unwrap();
+        assert_eq!((stored.access_token.as_str(), stored.refresh_token.as_deref()), ("fresh", Some("refresh")));
+        assert_eq!(stored.account.as_deref(), Some("me@example.com"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(dir.0.join(TOKEN_FILE)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_revoked_refresh_token_needs_a_new_login() {
+        let dir = TempDir::new();
+        let stub = drive_api(|request| match request.path.as_str() {
+            "/token" => Some(respond(400, r#"{"error": "invalid_grant"}"#)),
+            _ => None,
+        });
+        let drive = drive(&stub, Some(token(-10)), &dir.0);
+        assert!(matches!(drive.list().await, Err(CloudError::Auth(_))));
+    }
+
+    #[tokio::test]
+    async fn lists_every_page() {
+        let dir = TempDir::new();
+        let stub = drive_api(|request| match (request.path.as_str(), request.param("pageToken")) {
+            ("/drive/v3/files", None) if request.param("q").is_some_and(|q| q.contains("'folder' in parents")) => {
+                Some(json(json!({ "files": [file("1", "a.json", "3")], "nextPageToken": "next" })))
+            }
+            ("/drive/v3/files", Some(page)) if page == "next" => Some(json(json!({ "files": [file("2", "b.json", "5")] }))),
+            _ => None,
+        });
+        let drive = drive(&stub, Some(token(3600)), &dir.0);
+
+        let files = drive.list().await.unwrap();
+        let names: Vec<(&str, &str)> = files.iter().map(|file| (file.name.as_str(), file.version.as_str())).collect();
+        assert_eq!(names, vec![("a.json", "3"), ("b.json", "5")]);
+        assert_eq!(files[0].size, 2);
+        assert!(stub.received().iter().all(|request| request.header("authorization") == Some("Bearer access")));
+    }
+
+    #[tokio::test]
+    async fn uploads_new_and_changed_files() {
+        let dir = TempDir::new();
+        let stub = drive_api(|request| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/drive/v3/files") if request.param("q").is_some_and(|q| q.contains("name = 'new.json'")) => {
+                Some(json(json!({ "files": [] })))
+            }
+            ("GET", "/drive/v3/files") if request.param("q").is_some_and(|q| q.contains("name = 'old.json'")) => {
+                Some(json(json!({ "files": [file("old", "old.json", "7")] })))
+            }
+            ("POST", "/upload/drive/v3/files") => Some(json(file("new", "new.json", "1"))),
+            ("PATCH", "/upload/drive/v3/files/old") => Some(json(file("old", "old.json", "8"))),
+            _ => None,
+        });
+        let drive = drive(&stub, Some(token(3600)), &dir.0);
+
+        let meta = drive.put("new.json", b"{}".to_vec(), Expect::Missing).await.unwrap();
+        assert_eq!(meta.version, "1");
+        let created = stub.received().into_iter().find(|request| request.method == "POST").unwrap();
+        assert_eq!(created.param("uploadType").as_deref(), Some("multipart"));
+        assert!(created.text().contains(r#""name":"new.json""#));
+        assert!(created.text().contains(r#""parents":["folder"]"#));
+
+        let meta = drive.put("old.json", b"{}".to_vec(), Expect::Version("7".to_owned())).await.unwrap();
+        assert_eq!(meta.version, "8");
+        let changed = stub.received().into_iter().find(|request| request.method == "PATCH").unwrap();
+        assert_eq!(changed.text(), "{}");
+
+        // changed by someone else in the meantime
+        let result = drive.put("old.json", b"{}".to_vec(), Expect::Version("6".to_owned())).await;
+        assert!(matches!(result, Err(CloudError::Conflict(_))));
+        assert!(matches!(drive.put("old.json", Vec::new(), Expect::Missing).await, Err(CloudError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn deletes_files() {
+        let dir = TempDir::new();
+        let stub = drive_api(|request| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/drive/v3/files") if request.param("q").is_some_and(|q| q.contains("name = 'a.json'")) => {
+                Some(json(json!({ "files": [file("a", "a.json", "2")] })))
+            }
+            ("GET", "/drive/v3/files") if request.param("q").is_some_and(|q| q.contains("name = 'gone.json'")) => {
+                Some(json(json!({ "files": [] })))
+            }
+            ("DELETE", "/drive/v3/files/a") => Some(respond(204, "")),
+            _ => None,
+        });
+        let drive = drive(&stub, Some(token(3600)), &dir.0);
+
+        assert!(matches!(drive.delete("a.json", Expect::Version("1".to_owned())).await, Err(CloudError::Conflict(_))));
+        assert!(drive.delete("a.json", Expect::Version("2".to_owned())).await.unwrap());
+        assert!(!drive.delete("gone.json", Expect::Any).await.unwrap());
+        assert_eq!(stub.received().iter().filter(|request| request.method == "DELETE").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_account_is_part_of_the_name() {
+        let stub = drive_api(|_| None);
+        let name = |account: Option<&str>| {
+            let dir = TempDir::new();
+            let token = Token { account: account.map(str::to_owned), ..token(3600) };
+            drive(&stub, Some(token), &dir.0).name()
+        };
+        assert_ne!(name(Some("a@example.com")), name(Some("b@example.com")));
+        assert_eq!(name(None), "gdrive:Snow Treading");
+    }
+
+    // the login page of `stub` sends the browser straight back, with `state` unless it is forged
+    fn login_page(forged_state: Option<&'static str>) -> Stub {
+        Stub::start(move |request| match request.path.as_str() {
+            "/auth" => {
+                let state = forged_state.map(str::to_owned).or_else(|| request.param("state")).unwrap_or_default();
+                let redirect = request.param("redirect_uri").unwrap_or_default();
+                let back = Url::parse_with_params(&format!("{}/", redirect), &[("code", "the-code"), ("state", &state)]).unwrap();
+                let mut response = respond(302, "");
+                response.headers_mut().insert("location", back.as_str().parse().unwrap());
+                response
+            }
+            "/token" => json(json!({ "access_token": "access", "refresh_token": "refresh", "expires_in": 3600 })),
+            "/drive/v3/about" => json(json!({ "user": { "emailAddress": "me@example.com" } })),
+            _ => respond(404, "not found"),
+        })
+    }
+
+    // opens the login page like a browser would, after asking for a favicon like browsers do
+    fn browser(url: &str) {
+        let url = url.to_owned();
+        tokio::spawn(async move {
+            let redirect = Url::parse(&url).unwrap().query_pairs().find(|(key, _)| key == "redirect_uri").unwrap().1.into_owned();
+            let _ = reqwest::get(format!("{}/favicon.ico", redirect)).await;
+            let _ = reqwest::get(url).await;
+        });
+    }
+
+    #[tokio::test]
+    async fn logs_in_through_the_browser() {
+        let dir = TempDir::new();
+        let stub = login_page(None);
+        let drive = drive(&stub, None, &dir.0);
+        assert!(!drive.is_logged_in());
+
+        drive.login(browser).await.unwrap();
+        assert!(drive.is_logged_in());
+        let received = stub.received();
+        let page = received.iter().find(|request| request.path == "/auth").unwrap();
+        let token = received.iter().find(|request| request.path == "/token").unwrap();
+        assert_eq!(page.param("client_id").as_deref(), Some("client"));
+        assert_eq!(page.param("code_challenge_method").as_deref(), Some("S256"));
+        assert!(page.param("redirect_uri").unwrap().starts_with("http://127.0.0.1:"));
+
+        // the code comes back to the app, which proves it asked for it
+        assert_eq!(token.param("grant_type").as_deref(), Some("authorization_code"));
+        assert_eq!(token.param("code").as_deref(), Some("the-code"));
+        assert_eq!(token.param("redirect_uri"), page.param("redirect_uri"));
+        let verifier = token.param("code_verifier").unwrap();
+        let challenge = base64::encode_config(Sha256::digest(verifier.as_bytes()), base64::URL_SAFE_NO_PAD);
+        assert_eq!(page.param("code_challenge"), Some(challenge));
+
+        let about = received.iter().find(|request| request.path == "/drive/v3/about").unwrap();
+        assert_eq!(about.header("authorization"), Some("Bearer access"));
+        let stored: Token = read_json(&dir.0.join(TOKEN_FILE)).unwrap();
+        assert_eq!((stored.refresh_token.as_deref(), stored.account.as_deref()), (Some("refresh"), Some("me@example.com")));
+    }
+
+    #[tokio::test]
+    async fn a_login_answer_with_another_state_is_refused() {
+        let dir = TempDir::new();
+        let stub = login_page(Some("forged"));
+        let drive = drive(&stub, None, &dir.0);
+
+        assert!(matches!(drive.login(browser).await, Err(CloudError::Auth(_))));
+        assert!(!drive.is_logged_in());
+        assert!(stub.received().iter().all(|request| request.path != "/token"));
+        assert!(!dir.0.join(TOKEN_FILE).exists());
+    }
+}
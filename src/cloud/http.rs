@@ -0,0 +1,26 @@
+use super::CloudError;
+use reqwest::{Response, StatusCode};
+
+impl From<reqwest::Error> for CloudError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            CloudError::Invalid(e.to_string())
+        } else {
+            CloudError::Network(e.into())
+        }
+    }
+}
+
+// turns every answer that is not a success into an error, `name` is the file it was about
+pub(crate) async fn check(response: Response, name: &str) -> Result<Response, CloudError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = response.text().await.unwrap_or_default();
+    Err(match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => CloudError::Auth(message),
+        StatusCode::PRECONDITION_FAILED => CloudError::Conflict(name.to_owned()),
+        _ => CloudError::Status { status: status.as_u16(), message },
+    })
+}
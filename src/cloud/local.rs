@@ -0,0 +1,117 @@
+use super::{CloudError, CloudStorage, Expect, RemoteMeta};
+use crate::storage::{fnv1a, write_atomic};
+use crate::StorageError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+/// A [`CloudStorage`] in a local directory, e.g. one that is synced by some other program,
+/// or for trying out a sync without a server.
+pub struct LocalStorage {
+    dir: PathBuf,
+    // checking the expected version and writing happen as one step
+    write: Mutex<()>,
+}
+
+impl LocalStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        LocalStorage { dir: dir.into(), write: Mutex::new(()) }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, CloudError> {
+        // names come from the other side as well, they must not point outside of the directory
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(CloudError::Invalid(format!("'{}' is not a valid file name", name)));
+        }
+        Ok(self.dir.join(name))
+    }
+
+    fn read(&self, name: &str) -> Result<Option<(RemoteMeta, Vec<u8>)>, CloudError> {
+        let path = self.path(name)?;
+        match fs::read(&path) {
+            Ok(data) => Ok(Some((meta(name, &path, &data), data))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(StorageError::reading(&path, e).into()),
+        }
+    }
+
+    fn check(&self, name: &str, expect: &Expect) -> Result<Option<RemoteMeta>, CloudError> {
+        let current = self.read(name)?.map(|(meta, _)| meta);
+        let expected = match (expect, &current) {
+            (Expect::Any, _) | (Expect::Missing, None) => true,
+            (Expect::Version(version), Some(current)) => *version == current.version,
+            _ => false,
+        };
+        if !expected {
+            return Err(CloudError::Conflict(name.to_owned()));
+        }
+        Ok(current)
+    }
+}
+
+#[async_trait]
+impl CloudStorage for LocalStorage {
+    fn name(&self) -> String {
+        format!("local:{}", self.dir.display())
+    }
+
+    async fn list(&self) -> Result<Vec<RemoteMeta>, CloudError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(StorageError::reading(&self.dir, e).into()),
+        };
+        let mut files = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| StorageError::reading(&self.dir, e))?.path();
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_owned();
+            // half written files of `write_atomic`
+            if !path.is_file() || name.starts_with('.') || name.ends_with(".tmp") {
+                continue;
+            }
+            if let Some((meta, _)) = self.read(&name)? {
+                files.push(meta);
+            }
+        }
+        Ok(files)
+    }
+
+    async fn metadata(&self, name: &str) -> Result<Option<RemoteMeta>, CloudError> {
+        Ok(self.read(name)?.map(|(meta, _)| meta))
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<(RemoteMeta, Vec<u8>)>, CloudError> {
+        self.read(name)
+    }
+
+    async fn put(&self, name: &str, data: Vec<u8>, expect: Expect) -> Result<RemoteMeta, CloudError> {
+        let _write = self.write.lock().await;
+        self.check(name, &expect)?;
+        let path = self.path(name)?;
+        fs::create_dir_all(&self.dir).map_err(|e| StorageError::writing(&self.dir, e))?;
+        write_atomic(&path, &data)?;
+        Ok(meta(name, &path, &data))
+    }
+
+    async fn delete(&self, name: &str, expect: Expect) -> Result<bool, CloudError> {
+        let _write = self.write.lock().await;
+        if self.check(name, &expect)?.is_none() {
+            return Ok(false);
+        }
+        let path = self.path(name)?;
+        fs::remove_file(&path).map_err(|e| StorageError::writing(&path, e))?;
+        Ok(true)
+    }
+}
+
+fn meta(name: &str, path: &Path, data: &[u8]) -> RemoteMeta {
+    let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok().map(DateTime::<Utc>::from);
+    RemoteMeta { name: name.to_owned(), version: format!("{:016x}", fnv1a(data)), size: data.len() as u64, modified }
+}
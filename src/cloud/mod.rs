@@ -1 +1,48 @@
-pub mod cloud_storage;
\ No newline at end of file
+mod error;
+mod gdrive;
+mod http;
+mod local;
+mod s3;
+mod sync;
+mod syncer;
+mod webdav;
+pub mod cloud_storage;
+#[cfg(test)]
+mod stub;
+
+pub use cloud_storage::{CloudStorage, Expect, RemoteMeta};
+pub use error::CloudError;
+pub use gdrive::{Endpoints, GoogleCredentials, GoogleDrive};
+pub use local::LocalStorage;
+pub use s3::{S3Config, S3};
+pub use sync::{SyncEngine, SyncReport};
+pub use syncer::Syncer;
+pub use webdav::{WebDav, WebDavConfig};
+
+use serde::{Deserialize, Serialize};
+
+/// Where notes are synced to, chosen in the app config.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CloudProvider {
+    /// nowhere, the notes stay on this machine
+    #[default]
+    None,
+    /// the app folder in the user's Google Drive
+    GoogleDrive(GoogleCredentials),
+    /// a folder on a WebDAV server, e.g. Nextcloud
+    WebDav(WebDavConfig),
+    /// a bucket of Amazon S3, MinIO or another S3 compatible server
+    S3(S3Config),
+}
+
+impl CloudProvider {
+    /// The storage to sync with, `None` if the notes are not synced.
+    pub fn connect(&self) -> Result<Option<Box<dyn CloudStorage>>, CloudError> {
+        Ok(match self {
+            CloudProvider::None => None,
+            CloudProvider::GoogleDrive(credentials) => Some(Box::new(GoogleDrive::new(credentials.clone(), Endpoints::default())?)),
+            CloudProvider::WebDav(config) => Some(Box::new(WebDav::new(config.clone())?)),
+            CloudProvider::S3(config) => Some(Box::new(S3::new(config.clone())?)),
+        })
+    }
+}
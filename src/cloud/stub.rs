@@ -0,0 +1,97 @@
+//! A small http server standing in for the servers of the cloud backends in tests.
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Request, Response, Server, StatusCode};
+use reqwest::Url;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+/// A request as the stub received it.
+#[derive(Clone, Debug)]
+pub struct Received {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl Received {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    /// A parameter of the query, or of the body if it is a form.
+    pub fn param(&self, name: &str) -> Option<String> {
+        let form = String::from_utf8_lossy(&self.body);
+        [self.query.as_str(), &form].iter()
+            .filter_map(|params| Url::parse(&format!("http://stub/?{}", params)).ok())
+            .find_map(|url| url.query_pairs().find(|(key, _)| key == name).map(|(_, value)| value.into_owned()))
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Answers every request on a free local port with whatever the handler returns.
+/// Stops together with the runtime of the test.
+pub struct Stub {
+    url: String,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+impl Stub {
+    pub fn start(handler: impl FnMut(&Received) -> Response<Body> + Send + 'static) -> Self {
+        let handler = Arc::new(Mutex::new(handler));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let make = make_service_fn(move |_| {
+            let (handler, log) = (handler.clone(), log.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let (handler, log) = (handler.clone(), log.clone());
+                    async move {
+                        let (parts, body) = request.into_parts();
+                        let body = hyper::body::to_bytes(body).await.map(|body| body.to_vec()).unwrap_or_default();
+                        let request = Received {
+                            method: parts.method.to_string(),
+                            path: parts.uri.path().to_owned(),
+                            query: parts.uri.query().unwrap_or_default().to_owned(),
+                            headers: parts.headers,
+                            body,
+                        };
+                        let response = (handler.lock().unwrap())(&request);
+                        log.lock().unwrap().push(request);
+                        Ok::<_, Infallible>(response)
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        Stub { url, received }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Every request so far, oldest first.
+    pub fn received(&self) -> Vec<Received> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+pub fn respond(status: u16, body: impl Into<Body>) -> Response<Body> {
+    let mut response = Response::new(body.into());
+    *response.status_mut() = StatusCode::from_u16(status).unwrap();
+    response
+}
+
+pub fn json(value: serde_json::Value) -> Response<Body> {
+    let mut response = respond(200, value.to_string());
+    response.headers_mut().insert("content-type", "application/json".parse().unwrap());
+    response
+}
@@ -0,0 +1,674 @@
+use super::{CloudError, CloudStorage, Expect, RemoteMeta};
+use crate::storage::{data_dir, lock, read_json, upgrade_note, write_json, SharedStore, SCHEMA_VERSION};
+use crate::{Note, NoteId, NoteStore, StorageError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+// remembers what was synced last, inside the data dir
+const STATE_FILE: &str = "sync.json";
+
+// what a note looks like on the other side, revisions stay on the machine they were made on
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u64,
+    note: &'a Note,
+}
+
+/// A note as it was after it was last synced.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Synced {
+    // the version of the remote file
+    version: String,
+    // enough to tell whether the local note changed since
+    modified: DateTime<Utc>,
+    deleted: Option<DateTime<Utc>>,
+    // the note itself, what a change on both sides is merged against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    base: Option<Note>,
+}
+
+impl Synced {
+    fn of(note: &Note, version: String) -> Self {
+        let base = Note { revisions: Vec::new(), ..note.clone() };
+        Synced { version, modified: note.modified, deleted: note.deleted, base: Some(base) }
+    }
+
+    fn matches(&self, note: &Note) -> bool {
+        self.modified == note.modified && self.deleted == note.deleted
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct SyncState {
+    // the `CloudStorage::name` the state belongs to
+    remote: String,
+    notes: HashMap<NoteId, Synced>,
+}
+
+/// What a sync changed.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub uploaded: usize,
+    /// notes that were added or changed locally, already written to the store
+    pub downloaded: Vec<Note>,
+    /// notes that were removed locally, because they were removed on the other side
+    pub removed: Vec<NoteId>,
+    /// notes that were changed on both sides and merged, also listed in `downloaded`
+    pub merged: Vec<NoteId>,
+    /// notes that were changed on both sides in ways that could not be merged: the id of the
+    /// note, which now has the content of the other side, and of the copy of the local version
+    pub conflicts: Vec<(NoteId, NoteId)>,
+    /// notes that could not be synced this time, they are tried again on the next sync
+    pub failed: Vec<(NoteId, CloudError)>,
+}
+
+// what has to happen to a single note
+#[derive(Debug, PartialEq)]
+enum Action {
+    Nothing,
+    Upload,
+    Download,
+    RemoveLocal,
+    RemoveRemote,
+    // gone on both sides
+    Forget,
+    // changed on both sides, or new on both sides, see `SyncEngine::resolve`
+    Conflict,
+}
+
+fn plan(local: Option<&Note>, remote: Option<&RemoteMeta>, synced: Option<&Synced>) -> Action {
+    match (local, remote, synced) {
+        (Some(_), None, None) => Action::Upload,
+        (None, Some(_), None) => Action::Download,
+        (Some(_), Some(_), None) => Action::Conflict,
+        (Some(local), Some(remote), Some(synced)) => {
+            match (!synced.matches(local), remote.version != synced.version) {
+                (false, false) => Action::Nothing,
+                (true, false) => Action::Upload,
+                (false, true) => Action::Download,
+                (true, true) => Action::Conflict,
+            }
+        }
+        // removed on the other side, unless it was changed here in the meantime
+        (Some(local), None, Some(synced)) if synced.matches(local) => Action::RemoveLocal,
+        (Some(_), None, Some(_)) => Action::Upload,
+        // purged here, unless it was changed on the other side in the meantime
+        (None, Some(remote), Some(synced)) if remote.version == synced.version => Action::RemoveRemote,
+        (None, Some(_), Some(_)) => Action::Download,
+        (None, None, Some(_)) => Action::Forget,
+        (None, None, None) => Action::Nothing,
+    }
+}
+
+/// Keeps the notes in a [`NoteStore`] and a [`CloudStorage`] in sync. Every note is a file
+/// of its own on the other side. What was synced last is remembered, so a change on one
+/// side is told apart from a change on the other.
+pub struct SyncEngine {
+    remote: Box<dyn CloudStorage>,
+    state: SyncState,
+    state_file: PathBuf,
+}
+
+impl SyncEngine {
+    /// Picks up the state of the last sync with `remote`, if there was one.
+    pub fn new(remote: Box<dyn CloudStorage>) -> Result<Self, StorageError> {
+        let state_file = data_dir()?.join(STATE_FILE);
+        let state = match read_json::<SyncState>(&state_file) {
+            Ok(state) if state.remote == remote.name() => state,
+            // a different remote knows nothing about what was synced before
+            Ok(_) | Err(StorageError::Missing(_)) => SyncState { remote: remote.name(), notes: HashMap::new() },
+            Err(e) => return Err(e),
+        };
+        Ok(SyncEngine { remote, state, state_file })
+    }
+
+    /// Forgets what was synced with any remote, e.g. before logging in to another account.
+    /// The next sync compares both sides without assuming anything was removed.
+    pub fn reset() -> Result<(), StorageError> {
+        let state_file = data_dir()?.join(STATE_FILE);
+        match std::fs::remove_file(&state_file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(StorageError::writing(&state_file, e)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn remote(&self) -> &dyn CloudStorage {
+        self.remote.as_ref()
+    }
+
+    /// Syncs every note in `store` with the remote. Local changes are written to `store`
+    /// right away and listed in the report, so they can be shown.
+    pub async fn sync(&mut self, store: &SharedStore) -> Result<SyncReport, CloudError> {
+        let local: HashMap<NoteId, Note> = blocking(store, |store| store.load_all()).await?
+            .into_iter()
+            .map(|note| (note.id, note))
+            .collect();
+        let remote: HashMap<NoteId, RemoteMeta> = self.remote.list().await?
+            .into_iter()
+            .filter_map(|meta| Some((note_id(&meta.name)?, meta)))
+            .collect();
+        let ids: BTreeSet<NoteId> = local.keys().chain(remote.keys()).chain(self.state.notes.keys()).copied().collect();
+        // more likely a store that failed to load or was replaced than every note purged by hand
+        if local.is_empty() && ids.iter().any(|id| plan(None, remote.get(id), self.state.notes.get(id)) == Action::RemoveRemote) {
+            return Err(CloudError::Invalid("there are no notes here, not removing them on the other side as well".to_owned()));
+        }
+
+        let mut report = SyncReport::default();
+        // local writes are done together once every note was looked at
+        let mut stores = Vec::new();
+        let mut removes = Vec::new();
+        let mut fatal = None;
+        for id in ids {
+            let (local, remote) = (local.get(&id), remote.get(&id));
+            let action = plan(local, remote, self.state.notes.get(&id));
+            let result = match action {
+                Action::Nothing => Ok(()),
+                Action::Forget => {
+                    self.state.notes.remove(&id);
+                    Ok(())
+                }
+                Action::Upload => self.upload(local.unwrap(), remote, &mut report).await,
+                Action::Download => self.download(id, local, &mut stores).await,
+                Action::RemoveLocal => {
+                    removes.push(id);
+                    Ok(())
+                }
+                Action::RemoveRemote => self.remove_remote(id).await,
+                Action::Conflict => self.resolve(local.unwrap(), &mut stores, &mut report).await,
+            };
+            match result {
+                Ok(()) => {}
+                Err(e) if e.is_fatal() => {
+                    fatal = Some(e);
+                    break;
+                }
+                Err(e) => {
+                    warn!("[Snow]: could not sync note {}: {}", id, e);
+                    report.failed.push((id, e));
+                }
+            }
+        }
+
+        self.apply(store, &local, stores, removes, &mut report).await?;
+        write_json(&self.state_file, &self.state)?;
+        match fatal {
+            Some(e) => Err(e),
+            None => {
+                info!("[Snow]: synced with '{}': {} up, {} down, {} removed", self.remote.name(), report.uploaded, report.downloaded.len(), report.removed.len());
+                Ok(report)
+            }
+        }
+    }
+
+    // `remote` is the file as listed. one removed on the other side since the last sync is
+    // created again, it has no version left to expect
+    async fn upload(&mut self, note: &Note, remote: Option<&RemoteMeta>, report: &mut SyncReport) -> Result<(), CloudError> {
+        let expect = match (remote, self.state.notes.get(&note.id)) {
+            (Some(_), Some(synced)) => Expect::Version(synced.version.clone()),
+            _ => Expect::Missing,
+        };
+        self.put(note, expect).await?;
+        report.uploaded += 1;
+        Ok(())
+    }
+
+    async fn put(&mut self, note: &Note, expect: Expect) -> Result<(), CloudError> {
+        let meta = self.remote.put(&file_name(note.id), encode(note)?, expect).await?;
+        self.state.notes.insert(note.id, Synced::of(note, meta.version));
+        Ok(())
+    }
+
+    // fetches the remote note, the state is only updated once it was written locally
+    async fn download(&mut self, id: NoteId, local: Option<&Note>, stores: &mut Vec<(Note, Option<String>)>) -> Result<(), CloudError> {
+        let name = file_name(id);
+        let (meta, data) = match self.remote.get(&name).await? {
+            Some(file) => file,
+            // gone since it was listed, the next sync sorts it out
+            None => return Ok(()),
+        };
+        let mut note = decode(&name, &data)?;
+        if let Some(local) = local {
+            note.revisions = local.revisions.clone();
+        }
+        stores.push((note, Some(meta.version)));
+        Ok(())
+    }
+
+    async fn remove_remote(&mut self, id: NoteId) -> Result<(), CloudError> {
+        let expect = match self.state.notes.get(&id) {
+            Some(synced) => Expect::Version(synced.version.clone()),
+            None => Expect::Any,
+        };
+        self.remote.delete(&file_name(id), expect).await?;
+        self.state.notes.remove(&id);
+        Ok(())
+    }
+
+    // both sides changed the note, the changes are merged against the last synced version.
+    // if that fails the note takes the remote version and the local one becomes a copy
+    async fn resolve(&mut self, local: &Note, stores: &mut Vec<(Note, Option<String>)>, report: &mut SyncReport) -> Result<(), CloudError> {
+        let name = file_name(local.id);
+        let (meta, data) = match self.remote.get(&name).await? {
+            Some(file) => file,
+            None => return self.put(local, Expect::Missing).await,
+        };
+        let mut remote = decode(&name, &data)?;
+        if remote.same_content(local) && remote.deleted == local.deleted {
+            self.state.notes.insert(local.id, Synced::of(local, meta.version));
+            return Ok(());
+        }
+
+        let base = self.state.notes.get(&local.id).and_then(|synced| synced.base.as_ref());
+        if let Some(mut merged) = base.and_then(|base| local.merge(base, &remote)) {
+            info!("[Snow]: note {} was changed on both sides, merged the changes", local.id);
+            // written here only once the other side has it, a failed upload is merged again next time
+            let meta = self.remote.put(&name, encode(&merged)?, Expect::Version(meta.version)).await?;
+            merged.revisions = local.revisions.clone();
+            stores.push((merged, Some(meta.version)));
+            report.uploaded += 1;
+            report.merged.push(local.id);
+            return Ok(());
+        }
+
+        let copy = local.conflicted_copy();
+        warn!("[Snow]: note {} was changed on both sides and could not be merged, kept the local version as {}", local.id, copy.id);
+        report.conflicts.push((local.id, copy.id));
+        remote.revisions = local.revisions.clone();
+        stores.push((remote, Some(meta.version)));
+        // uploaded with the next sync like any other new note
+        stores.push((copy, None));
+        Ok(())
+    }
+
+    // writes the downloads and removals to the store. Notes that were saved there since
+    // `local` was read are left alone, the next sync sees them as changed on both sides
+    async fn apply(&mut self, store: &SharedStore, local: &HashMap<NoteId, Note>, stores: Vec<(Note, Option<String>)>, removes: Vec<NoteId>, report: &mut SyncReport) -> Result<(), CloudError> {
+        if stores.is_empty() && removes.is_empty() {
+            return Ok(());
+        }
+        let seen = |id: &NoteId| local.get(id).map(|note| (note.modified, note.deleted));
+        let notes: Vec<(Note, Option<Seen>)> = stores.iter().map(|(note, _)| (note.clone(), seen(&note.id))).collect();
+        let ids: Vec<(NoteId, Option<Seen>)> = removes.iter().map(|id| (*id, seen(id))).collect();
+        // a conflicted copy is only kept if the note it was made of gets the other version
+        let copies: HashMap<NoteId, NoteId> = report.conflicts.iter().map(|(original, copy)| (*copy, *original)).collect();
+        let skipped = blocking(store, move |store| {
+            let mut skipped = Vec::new();
+            for (note, seen) in &notes {
+                let original = copies.get(&note.id).is_some_and(|original| skipped.contains(original));
+                let now = store.get(note.id)?.map(|note| (note.modified, note.deleted));
+                if original || now != *seen {
+                    skipped.push(note.id);
+                    continue;
+                }
+                store.upsert(note)?;
+            }
+            for (id, seen) in &ids {
+                if store.get(*id)?.map(|note| (note.modified, note.deleted)) != *seen {
+                    skipped.push(*id);
+                    continue;
+                }
+                store.delete(*id)?;
+            }
+            Ok(skipped)
+        }).await?;
+        report.conflicts.retain(|(original, _)| !skipped.contains(original));
+        report.merged.retain(|id| !skipped.contains(id));
+        if !skipped.is_empty() {
+            info!("[Snow]: {} note(s) were saved during the sync, they are synced next time", skipped.len());
+        }
+
+        for (note, version) in stores {
+            if skipped.contains(&note.id) {
+                continue;
+            }
+            if let Some(version) = version {
+                self.state.notes.insert(note.id, Synced::of(&note, version));
+            }
+            report.downloaded.push(note);
+        }
+        for id in removes {
+            if skipped.contains(&id) {
+                continue;
+            }
+            self.state.notes.remove(&id);
+            report.removed.push(id);
+        }
+        Ok(())
+    }
+}
+
+// when a note was last modified and trashed, to tell whether it changed in the meantime
+type Seen = (DateTime<Utc>, Option<DateTime<Utc>>);
+
+// runs `f` on the store without blocking the async runtime
+async fn blocking<T: Send + 'static>(
+    store: &SharedStore,
+    f: impl FnOnce(&mut dyn NoteStore) -> Result<T, StorageError> + Send + 'static,
+) -> Result<T, CloudError> {
+    let store = store.clone();
+    tokio::task::spawn_blocking(move || f(lock(&store).as_mut()))
+        .await
+        .map_err(|e| CloudError::Invalid(format!("the sync crashed: {}", e)))?
+        .map_err(CloudError::from)
+}
+
+fn file_name(id: NoteId) -> String {
+    format!("{}.json", id)
+}
+
+fn note_id(name: &str) -> Option<NoteId> {
+    name.strip_suffix(".json")?.parse().ok()
+}
+
+fn encode(note: &Note) -> Result<Vec<u8>, CloudError> {
+    let note = Note { revisions: Vec::new(), ..note.clone() };
+    serde_json::to_vec_pretty(&Envelope { version: SCHEMA_VERSION, note: &note })
+        .map_err(|e| CloudError::Invalid(e.to_string()))
+}
+
+// notes written by older builds on another machine are upgraded like local ones
+fn decode(name: &str, data: &[u8]) -> Result<Note, CloudError> {
+    let invalid = |e: &dyn std::fmt::Display| CloudError::Invalid(format!("'{}' is not a note: {}", name, e));
+    let mut envelope: serde_json::Map<String, Value> = serde_json::from_slice(data).map_err(|e| invalid(&e))?;
+    let version = envelope.get("version").and_then(Value::as_u64).unwrap_or(0);
+    let note = upgrade_note(envelope.remove("note").unwrap_or(Value::Null), version, Path::new(name))?;
+    if note_id(name) != Some(note.id) {
+        return Err(invalid(&"the id does not match the file name"));
+    }
+    Ok(note)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloud::LocalStorage;
+    use crate::storage::{set_data_dir, shared, MemoryStore, NoteMeta};
+    use chrono::Duration;
+    use std::fs;
+    use std::sync::Mutex;
+
+    fn note(text: &str) -> Note {
+        Note::new(NoteId::new(), text.to_string(), "title".to_string(), [0, 0, 0])
+    }
+
+    fn meta(version: &str) -> RemoteMeta {
+        RemoteMeta { name: String::new(), version: version.to_string(), size: 0, modified: None }
+    }
+
+    fn synced(note: &Note, version: &str) -> Synced {
+        Synced::of(note, version.to_string())
+    }
+
+    fn changed(note: &Note) -> Note {
+        let mut note = note.clone();
+        note.modified += Duration::seconds(1);
+        note
+    }
+
+    #[test]
+    fn plan_without_a_previous_sync() {
+        let note = note("a");
+        assert_eq!(plan(None, None, None), Action::Nothing);
+        assert_eq!(plan(Some(&note), None, None), Action::Upload);
+        assert_eq!(plan(None, Some(&meta("1")), None), Action::Download);
+        assert_eq!(plan(Some(&note), Some(&meta("1")), None), Action::Conflict);
+    }
+
+    #[test]
+    fn plan_on_both_sides() {
+        let note = note("a");
+        let synced = synced(&note, "1");
+        assert_eq!(plan(Some(&note), Some(&meta("1")), Some(&synced)), Action::Nothing);
+        assert_eq!(plan(Some(&changed(&note)), Some(&meta("1")), Some(&synced)), Action::Upload);
+        assert_eq!(plan(Some(&note), Some(&meta("2")), Some(&synced)), Action::Download);
+        assert_eq!(plan(Some(&changed(&note)), Some(&meta("2")), Some(&synced)), Action::Conflict);
+    }
+
+    #[test]
+    fn plan_trashing_is_a_change() {
+        let note = note("a");
+        let synced = synced(&note, "1");
+        let trashed = Note { deleted: Some(Utc::now()), ..note.clone() };
+        assert_eq!(plan(Some(&trashed), Some(&meta("1")), Some(&synced)), Action::Upload);
+    }
+
+    #[test]
+    fn plan_removed_remotely() {
+        let note = note("a");
+        let synced = synced(&note, "1");
+        assert_eq!(plan(Some(&note), None, Some(&synced)), Action::RemoveLocal);
+        // changed here since, so it comes back
+        assert_eq!(plan(Some(&changed(&note)), None, Some(&synced)), Action::Upload);
+    }
+
+    #[test]
+    fn plan_removed_locally() {
+        let note = note("a");
+        let synced = synced(&note, "1");
+        assert_eq!(plan(None, Some(&meta("1")), Some(&synced)), Action::RemoveRemote);
+        // changed on the other side since, so it comes back
+        assert_eq!(plan(None, Some(&meta("2")), Some(&synced)), Action::Download);
+    }
+
+    #[test]
+    fn plan_removed_on_both_sides() {
+        let note = note("a");
+        assert_eq!(plan(None, None, Some(&synced(&note, "1"))), Action::Forget);
+    }
+
+    // the data dir is global, an engine only needs it to find its state file when it is created
+    static DATA_DIR: Mutex<()> = Mutex::new(());
+
+    // a data dir and a remote folder for one test, removed again once it is done
+    struct TempDirs(PathBuf);
+
+    impl TempDirs {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("snow-treading-sync-{}", NoteId::new()));
+            fs::create_dir_all(dir.join("remote")).unwrap();
+            TempDirs(dir)
+        }
+
+        // an engine with a data dir of its own, like on another device
+        fn device(&self, name: &str) -> SyncEngine {
+            let dir = self.0.join(name);
+            fs::create_dir_all(&dir).unwrap();
+            let _data_dir = DATA_DIR.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            set_data_dir(&dir);
+            SyncEngine::new(Box::new(LocalStorage::new(self.0.join("remote")))).unwrap()
+        }
+    }
+
+    impl Drop for TempDirs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn notes(store: &SharedStore) -> Vec<Note> {
+        lock(store).load_all().unwrap()
+    }
+
+    #[tokio::test]
+    async fn round_trip_between_two_devices() {
+        let dirs = TempDirs::new();
+        let (mut a, mut b) = (dirs.device("a"), dirs.device("b"));
+        let (original, other) = (note("first line\n"), note("other"));
+        let store_a = shared(Box::new(MemoryStore::with_notes(vec![original.clone(), other.clone()])));
+        let store_b = shared(Box::new(MemoryStore::new()));
+        let find = |store: &SharedStore, id: NoteId| lock(store).get(id).unwrap();
+
+        assert_eq!(a.sync(&store_a).await.unwrap().uploaded, 2);
+        let report = b.sync(&store_b).await.unwrap();
+        assert_eq!(report.downloaded.len(), 2);
+        assert_eq!(find(&store_b, original.id), Some(original.clone()));
+        assert_eq!(find(&store_b, other.id), Some(other.clone()));
+
+        // changed on b, picked up by a
+        let mut edited = original.clone();
+        edited.set_text("first line\nsecond line\n");
+        lock(&store_b).upsert(&edited).unwrap();
+        assert_eq!(b.sync(&store_b).await.unwrap().uploaded, 1);
+        a.sync(&store_a).await.unwrap();
+        assert_eq!(find(&store_a, original.id).unwrap().text, edited.text);
+
+        // nothing changed, nothing happens
+        let report = a.sync(&store_a).await.unwrap();
+        assert_eq!((report.uploaded, report.downloaded.len()), (0, 0));
+
+        // purged on a, removed on b
+        lock(&store_a).delete(original.id).unwrap();
+        a.sync(&store_a).await.unwrap();
+        let report = b.sync(&store_b).await.unwrap();
+        assert_eq!(report.removed, vec![original.id]);
+        assert_eq!(notes(&store_b), vec![other]);
+    }
+
+    #[tokio::test]
+    async fn a_note_changed_after_it_was_removed_remotely_comes_back() {
+        let dirs = TempDirs::new();
+        let (mut a, mut b) = (dirs.device("a"), dirs.device("b"));
+        let (original, other) = (note("text"), note("other"));
+        let store_a = shared(Box::new(MemoryStore::with_notes(vec![original.clone(), other.clone()])));
+        let store_b = shared(Box::new(MemoryStore::new()));
+        a.sync(&store_a).await.unwrap();
+        b.sync(&store_b).await.unwrap();
+
+        // purged on b while a still edits it
+        lock(&store_b).delete(original.id).unwrap();
+        b.sync(&store_b).await.unwrap();
+        let mut edited = original.clone();
+        edited.set_text("more text");
+        lock(&store_a).upsert(&edited).unwrap();
+
+        let report = a.sync(&store_a).await.unwrap();
+        assert!(report.failed.is_empty(), "{:?}", report.failed);
+        assert_eq!(report.uploaded, 1);
+        let report = a.sync(&store_a).await.unwrap();
+        assert_eq!((report.uploaded, report.failed.len()), (0, 0));
+
+        b.sync(&store_b).await.unwrap();
+        assert_eq!(lock(&store_b).get(original.id).unwrap().map(|note| note.text), Some(edited.text));
+    }
+
+    #[tokio::test]
+    async fn an_empty_store_does_not_remove_everything() {
+        let dirs = TempDirs::new();
+        let mut a = dirs.device("a");
+        let store = shared(Box::new(MemoryStore::with_notes(vec![note("a"), note("b")])));
+        a.sync(&store).await.unwrap();
+
+        // e.g. the notes could not be loaded
+        *lock(&store) = Box::new(MemoryStore::new());
+        assert!(matches!(a.sync(&store).await, Err(CloudError::Invalid(_))));
+        assert_eq!(fs::read_dir(dirs.0.join("remote")).unwrap().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn changes_on_both_sides_are_merged_or_copied() {
+        let dirs = TempDirs::new();
+        let (mut a, mut b) = (dirs.device("a"), dirs.device("b"));
+        let original = note("one\ntwo\nthree\n");
+        let store_a = shared(Box::new(MemoryStore::with_notes(vec![original.clone()])));
+        let store_b = shared(Box::new(MemoryStore::new()));
+        a.sync(&store_a).await.unwrap();
+        b.sync(&store_b).await.unwrap();
+
+        // different lines merge
+        let edit = |store: &SharedStore, text: &str| {
+            let mut note = lock(store).get(original.id).unwrap().unwrap();
+            note.set_text(text);
+            lock(store).upsert(&note).unwrap();
+        };
+        edit(&store_a, "one!\ntwo\nthree\n");
+        edit(&store_b, "one\ntwo\nthree!\n");
+        a.sync(&store_a).await.unwrap();
+        let report = b.sync(&store_b).await.unwrap();
+        assert_eq!(report.merged, vec![original.id]);
+        assert_eq!(notes(&store_b)[0].text, "one!\ntwo\nthree!\n");
+        a.sync(&store_a).await.unwrap();
+        assert_eq!(notes(&store_a)[0].text, "one!\ntwo\nthree!\n");
+
+        // the same line does not, b keeps a's version and a copy of its own
+        edit(&store_a, "one!\ntwo a\nthree!\n");
+        edit(&store_b, "one!\ntwo b\nthree!\n");
+        a.sync(&store_a).await.unwrap();
+        let report = b.sync(&store_b).await.unwrap();
+        let (id, copy) = report.conflicts[0];
+        assert_eq!(id, original.id);
+        let notes_b = notes(&store_b);
+        assert_eq!(notes_b.iter().find(|note| note.id == id).unwrap().text, "one!\ntwo a\nthree!\n");
+        let copy = notes_b.iter().find(|note| note.id == copy).unwrap();
+        assert_eq!(copy.text, "one!\ntwo b\nthree!\n");
+        assert!(copy.title.ends_with(Note::CONFLICTED_COPY));
+
+        // the copy goes up with the next sync
+        assert_eq!(b.sync(&store_b).await.unwrap().uploaded, 1);
+        a.sync(&store_a).await.unwrap();
+        assert_eq!(notes(&store_a).len(), 2);
+    }
+
+    // a store that gets a note saved right after the sync read it, like the storage worker would
+    struct SavedDuringSync {
+        inner: MemoryStore,
+        save: Option<Note>,
+    }
+
+    impl NoteStore for SavedDuringSync {
+        fn load_all(&mut self) -> Result<Vec<Note>, StorageError> {
+            let notes = self.inner.load_all()?;
+            if let Some(note) = self.save.take() {
+                self.inner.upsert(&note)?;
+            }
+            Ok(notes)
+        }
+
+        fn get(&self, id: NoteId) -> Result<Option<Note>, StorageError> {
+            self.inner.get(id)
+        }
+
+        fn upsert(&mut self, note: &Note) -> Result<(), StorageError> {
+            self.inner.upsert(note)
+        }
+
+        fn delete(&mut self, id: NoteId) -> Result<bool, StorageError> {
+            self.inner.delete(id)
+        }
+
+        fn list(&self) -> Result<Vec<NoteMeta>, StorageError> {
+            self.inner.list()
+        }
+    }
+
+    #[tokio::test]
+    async fn notes_saved_during_a_sync_are_not_overwritten() {
+        let dirs = TempDirs::new();
+        let (mut a, mut b) = (dirs.device("a"), dirs.device("b"));
+        let original = note("one\ntwo\n");
+        let store_a = shared(Box::new(MemoryStore::with_notes(vec![original.clone()])));
+        let store_b = shared(Box::new(MemoryStore::new()));
+        a.sync(&store_a).await.unwrap();
+        b.sync(&store_b).await.unwrap();
+
+        let mut theirs = original.clone();
+        theirs.set_text("one\ntwo\nthree\n");
+        lock(&store_a).upsert(&theirs).unwrap();
+        a.sync(&store_a).await.unwrap();
+
+        // b downloads a's change while the user saves one of their own
+        let mut ours = original.clone();
+        ours.set_text("zero\none\ntwo\n");
+        let inner = MemoryStore::with_notes(notes(&store_b));
+        *lock(&store_b) = Box::new(SavedDuringSync { inner, save: Some(ours.clone()) });
+        let report = b.sync(&store_b).await.unwrap();
+        assert!(report.downloaded.is_empty());
+        assert_eq!(notes(&store_b)[0].text, ours.text);
+
+        // and the next sync merges both
+        b.sync(&store_b).await.unwrap();
+        assert_eq!(notes(&store_b)[0].text, "zero\none\ntwo\nthree\n");
+    }
+}
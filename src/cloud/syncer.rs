@@ -0,0 +1,59 @@
+use super::{CloudError, SyncEngine, SyncReport};
+use crate::storage::SharedStore;
+use crate::toast::Notifier;
+use std::sync::mpsc as std_mpsc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Runs a [`SyncEngine`] on a background task, every `interval` and whenever asked to.
+pub struct Syncer {
+    requests: mpsc::UnboundedSender<()>,
+    results: std_mpsc::Receiver<Result<SyncReport, CloudError>>,
+    name: String,
+}
+
+impl Syncer {
+    /// Starts syncing on the current tokio runtime, right away and then every `interval`.
+    /// `notifier` wakes up the ui once a sync finished.
+    pub fn spawn(mut engine: SyncEngine, store: SharedStore, notifier: Notifier, interval: Duration) -> Self {
+        let (requests, mut rx) = mpsc::unbounded_channel();
+        let (results_tx, results) = std_mpsc::channel();
+        let name = engine.remote().name();
+        tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = timer.tick() => {}
+                    request = rx.recv() => match request {
+                        Some(()) => timer.reset(),
+                        // the syncer was dropped
+                        None => break,
+                    },
+                }
+                let result = engine.sync(&store).await;
+                if let Err(e) = &result {
+                    error!("[Snow]: could not sync with '{}': {}", engine.remote().name(), e);
+                }
+                if results_tx.send(result).is_err() {
+                    break;
+                }
+                notifier.wake();
+            }
+        });
+        Syncer { requests, results, name }
+    }
+
+    /// Where the notes are synced to, see [`CloudStorage::name`](super::CloudStorage::name).
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn sync_now(&self) {
+        let _ = self.requests.send(());
+    }
+
+    /// The syncs that finished since the last call.
+    pub fn results(&self) -> Vec<Result<SyncReport, CloudError>> {
+        self.results.try_iter().collect()
+    }
+}
@@ -0,0 +1,392 @@
+use super::http::check;
+use super::{CloudError, CloudStorage, Expect, RemoteMeta};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::header::{HeaderMap, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH};
+use reqwest::{Client, Method, RequestBuilder, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+
+// the properties a listing asks for
+const PROPFIND: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:">
+  <d:prop><d:getetag/><d:getcontentlength/><d:getlastmodified/><d:resourcetype/></d:prop>
+</d:propfind>"#;
+
+/// Where a [`WebDav`] server is and how to log in to it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WebDavConfig {
+    /// the folder the notes go into, e.g. `https://cloud.example.com/remote.php/dav/files/me/notes/`
+    pub url: String,
+    pub user: String,
+    /// for nextcloud an app password, not the one of the account. Ends up in the config file,
+    /// which is only readable by the user.
+    pub password: String,
+}
+
+/// Syncs notes into a folder on any WebDAV server, e.g. Nextcloud. Changes are told apart
+/// by their ETag, and every write only goes through if the file still has the ETag it was
+/// last seen with.
+pub struct WebDav {
+    client: Client,
+    config: WebDavConfig,
+    // always ends with a slash, so file names can be joined onto it
+    folder: Url,
+}
+
+impl WebDav {
+    pub fn new(config: WebDavConfig) -> Result<Self, CloudError> {
+        let mut url = config.url.clone();
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+        let folder = Url::parse(&url).map_err(|e| CloudError::Invalid(format!("'{}' is not a valid url: {}", config.url, e)))?;
+        Ok(WebDav { client: Client::new(), config, folder })
+    }
+
+    fn url(&self, name: &str) -> Result<Url, CloudError> {
+        // names come from the other side as well, they must stay inside the folder
+        if name.is_empty() || name.contains('/') || name.starts_with('.') {
+            return Err(CloudError::Invalid(format!("'{}' is not a valid file name", name)));
+        }
+        self.folder.join(name).map_err(|e| CloudError::Invalid(e.to_string()))
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        if self.config.user.is_empty() {
+            return request;
+        }
+        request.basic_auth(&self.config.user, Some(&self.config.password))
+    }
+
+    async fn propfind(&self, url: Url, depth: &str) -> Result<Option<Vec<Entry>>, CloudError> {
+        let response = self.request(Method::from_bytes(b"PROPFIND").unwrap(), url)
+            .header("Depth", depth)
+            .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(PROPFIND)
+            .send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = check(response, self.folder.as_str()).await?.text().await?;
+        parse_multistatus(&body).map(Some)
+    }
+
+    // creates the folder, e.g. before the first note is written into it
+    async fn create_folder(&self) -> Result<(), CloudError> {
+        let response = self.request(Method::from_bytes(b"MKCOL").unwrap(), self.folder.clone()).send().await?;
+        // someone else was quicker
+        if response.status() == StatusCode::METHOD_NOT_ALLOWED {
+            return Ok(());
+        }
+        check(response, self.folder.as_str()).await?;
+        info!("[Snow]: created the folder '{}'", self.folder);
+        Ok(())
+    }
+
+    fn with_expect(request: RequestBuilder, expect: &Expect) -> RequestBuilder {
+        match expect {
+            Expect::Any => request,
+            Expect::Missing => request.header(IF_NONE_MATCH, "*"),
+            Expect::Version(etag) => request.header(IF_MATCH, etag.as_str()),
+        }
+    }
+}
+
+#[async_trait]
+impl CloudStorage for WebDav {
+    fn name(&self) -> String {
+        format!("webdav:{}", self.folder)
+    }
+
+    async fn list(&self) -> Result<Vec<RemoteMeta>, CloudError> {
+        let entries = self.propfind(self.folder.clone(), "1").await?.unwrap_or_default();
+        Ok(entries.into_iter().filter(|entry| !entry.folder).filter_map(Entry::meta).collect())
+    }
+
+    async fn metadata(&self, name: &str) -> Result<Option<RemoteMeta>, CloudError> {
+        let entries = self.propfind(self.url(name)?, "0").await?;
+        Ok(entries.and_then(|entries| entries.into_iter().find(|entry| !entry.folder)).and_then(Entry::meta))
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<(RemoteMeta, Vec<u8>)>, CloudError> {
+        let response = self.request(Method::GET, self.url(name)?).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = check(response, name).await?;
+        let etag = etag(response.headers());
+        let data = response.bytes().await?.to_vec();
+        let meta = match etag {
+            Some(version) => RemoteMeta { name: name.to_owned(), version, size: data.len() as u64, modified: None },
+            // some servers only hand out etags in listings
+            None => self.metadata(name).await?.ok_or_else(|| CloudError::Conflict(name.to_owned()))?,
+        };
+        Ok(Some((meta, data)))
+    }
+
+    async fn put(&self, name: &str, data: Vec<u8>, expect: Expect) -> Result<RemoteMeta, CloudError> {
+        let url = self.url(name)?;
+        let send = || Self::with_expect(self.request(Method::PUT, url.clone()), &expect)
+            .header(CONTENT_TYPE, "application/json")
+            .body(data.clone())
+            .send();
+        let mut response = send().await?;
+        // the folder does not exist yet
+        if response.status() == StatusCode::CONFLICT || response.status() == StatusCode::NOT_FOUND {
+            self.create_folder().await?;
+            response = send().await?;
+        }
+        let response = check(response, name).await?;
+        match etag(response.headers()) {
+            Some(version) => Ok(RemoteMeta { name: name.to_owned(), version, size: data.len() as u64, modified: None }),
+            None => self.metadata(name).await?.ok_or_else(|| CloudError::Conflict(name.to_owned())),
+        }
+    }
+
+    async fn delete(&self, name: &str, expect: Expect) -> Result<bool, CloudError> {
+        let request = Self::with_expect(self.request(Method::DELETE, self.url(name)?), &expect);
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return match expect {
+                Expect::Version(_) => Err(CloudError::Conflict(name.to_owned())),
+                _ => Ok(false),
+            };
+        }
+        check(response, name).await?;
+        Ok(true)
+    }
+}
+
+fn etag(headers: &HeaderMap) -> Option<String> {
+    headers.get(ETAG).and_then(|etag| etag.to_str().ok()).map(str::to_owned)
+}
+
+// one `<d:response>` of a PROPFIND answer
+#[derive(Default, Debug)]
+struct Entry {
+    href: String,
+    etag: Option<String>,
+    size: u64,
+    modified: Option<DateTime<Utc>>,
+    folder: bool,
+}
+
+impl Entry {
+    fn meta(self) -> Option<RemoteMeta> {
+        let name = self.href.trim_end_matches('/').rsplit('/').next()?;
+        let name = percent_decode(name);
+        Some(RemoteMeta { name, version: self.etag?, size: self.size, modified: self.modified })
+    }
+}
+
+// reads the entries of a `207 Multi-Status` answer, whatever namespace prefix the server uses
+fn parse_multistatus(xml: &str) -> Result<Vec<Entry>, CloudError> {
+    let invalid = |e: quick_xml::Error| CloudError::Invalid(format!("could not read the folder listing: {}", e));
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut entries = Vec::new();
+    let mut entry: Option<Entry> = None;
+    let mut element = Vec::new();
+    loop {
+        match reader.read_event(&mut buf).map_err(invalid)? {
+            Event::Start(start) => {
+                element = start.local_name().to_vec();
+                if element == b"response" {
+                    entry = Some(Entry::default());
+                }
+            }
+            Event::Empty(empty) => {
+                if let (b"collection", Some(entry)) = (empty.local_name(), entry.as_mut()) {
+                    entry.folder = true;
+                }
+            }
+            Event::Text(text) => {
+                let text = text.unescape_and_decode(&reader).map_err(invalid)?;
+                if let Some(entry) = entry.as_mut() {
+                    match element.as_slice() {
+                        b"href" => entry.href = text,
+                        b"getetag" => entry.etag = Some(text),
+                        b"getcontentlength" => entry.size = text.parse().unwrap_or(0),
+                        b"getlastmodified" => {
+                            entry.modified = DateTime::parse_from_rfc2822(&text).ok().map(|date| date.with_timezone(&Utc))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Event::End(end) => {
+                if end.local_name() == b"response" {
+                    entries.extend(entry.take());
+                }
+                element.clear();
+            }
+            Event::Eof => return Ok(entries),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+pub(crate) fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloud::stub::{respond, Received, Stub};
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    // a webdav folder at /dav/notes/ that only exists after the first MKCOL
+    #[derive(Default)]
+    struct Server {
+        folder: bool,
+        files: BTreeMap<String, (String, Vec<u8>)>,
+        etags: u32,
+    }
+
+    impl Server {
+        fn handle(&mut self, request: &Received) -> hyper::Response<hyper::Body> {
+            if request.header("authorization") != Some("Basic bWU6c2VjcmV0") {
+                return respond(401, "");
+            }
+            let name = request.path.strip_prefix("/dav/notes/").map(percent_decode);
+            let current = name.as_ref().and_then(|name| self.files.get(name)).map(|(etag, _)| etag.clone());
+            let allowed = match (request.header("if-match"), request.header("if-none-match")) {
+                (Some(etag), _) => current.as_deref() == Some(etag),
+                (_, Some("*")) => current.is_none(),
+                _ => true,
+            };
+            match (request.method.as_str(), name) {
+                ("MKCOL", Some(name)) if name.is_empty() => {
+                    self.folder = true;
+                    respond(201, "")
+                }
+                ("PROPFIND", Some(name)) if name.is_empty() && self.folder => respond(207, self.listing()),
+                // only writes tell a missing folder apart
+                ("PUT", Some(_)) if !self.folder => respond(409, ""),
+                (_, Some(_)) if !self.folder => respond(404, ""),
+                (_, _) if !allowed => respond(412, ""),
+                ("PUT", Some(name)) => {
+                    self.etags += 1;
+                    let etag = format!("\"{}\"", self.etags);
+                    self.files.insert(name, (etag.clone(), request.body.clone()));
+                    let mut response = respond(if current.is_some() { 204 } else { 201 }, "");
+                    response.headers_mut().insert("etag", etag.parse().unwrap());
+                    response
+                }
+                ("GET", Some(name)) => match self.files.get(&name) {
+                    Some((etag, data)) => {
+                        let mut response = respond(200, data.clone());
+                        response.headers_mut().insert("etag", etag.parse().unwrap());
+                        response
+                    }
+                    None => respond(404, ""),
+                },
+                ("DELETE", Some(name)) => match self.files.remove(&name) {
+                    Some(_) => respond(204, ""),
+                    None => respond(404, ""),
+                },
+                _ => respond(404, ""),
+            }
+        }
+
+        fn listing(&self) -> String {
+            let mut xml = String::from(r#"<?xml version="1.0"?><d:multistatus xmlns:d="DAV:">"#);
+            xml.push_str("<d:response><d:href>/dav/notes/</d:href><d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat></d:response>");
+            for (name, (etag, data)) in &self.files {
+                xml.push_str(&format!(
+                    "<d:response><d:href>/dav/notes/{}</d:href><d:propstat><d:prop><d:getetag>{}</d:getetag><d:getcontentlength>{}</d:getcontentlength>\
+                     <d:getlastmodified>Tue, 01 Mar 2022 09:30:00 GMT</d:getlastmodified><d:resourcetype/></d:prop></d:propstat></d:response>",
+                    name.replace(' ', "%20"), etag.replace('"', "&quot;"), data.len()
+                ));
+            }
+            xml.push_str("</d:multistatus>");
+            xml
+        }
+    }
+
+    fn server() -> (Stub, Arc<Mutex<Server>>, WebDav) {
+        let server = Arc::new(Mutex::new(Server::default()));
+        let handler = server.clone();
+        let stub = Stub::start(move |request| handler.lock().unwrap().handle(request));
+        let config = WebDavConfig { url: format!("{}/dav/notes", stub.url()), user: "me".to_owned(), password: "secret".to_owned() };
+        let dav = WebDav::new(config).unwrap();
+        (stub, server, dav)
+    }
+
+    #[tokio::test]
+    async fn uploads_downloads_and_deletes() {
+        let (stub, server, dav) = server();
+        assert!(dav.list().await.unwrap().is_empty());
+
+        // the first upload creates the folder
+        let meta = dav.put("a note.json", b"one".to_vec(), Expect::Missing).await.unwrap();
+        assert!(server.lock().unwrap().folder);
+        assert_eq!(stub.received().iter().filter(|request| request.method == "MKCOL").count(), 1);
+
+        let (got, data) = dav.get("a note.json").await.unwrap().unwrap();
+        assert_eq!((got.version, data), (meta.version.clone(), b"one".to_vec()));
+        assert!(dav.get("missing.json").await.unwrap().is_none());
+
+        let listed = dav.list().await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!((listed[0].name.as_str(), &listed[0].version, listed[0].size), ("a note.json", &meta.version, 3));
+        assert!(listed[0].modified.is_some());
+
+        let changed = dav.put("a note.json", b"two".to_vec(), Expect::Version(meta.version)).await.unwrap();
+        assert!(dav.delete("a note.json", Expect::Version(changed.version)).await.unwrap());
+        assert!(!dav.delete("a note.json", Expect::Any).await.unwrap());
+        assert!(dav.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn writes_only_go_through_with_the_etag_they_expect() {
+        let (stub, _server, dav) = server();
+        let first = dav.put("a.json", b"one".to_vec(), Expect::Missing).await.unwrap();
+        let second = dav.put("a.json", b"two".to_vec(), Expect::Any).await.unwrap();
+        assert_ne!(first.version, second.version);
+
+        // someone else wrote in between
+        assert!(matches!(dav.put("a.json", b"three".to_vec(), Expect::Missing).await, Err(CloudError::Conflict(_))));
+        assert!(matches!(dav.delete("a.json", Expect::Version(first.version.clone())).await, Err(CloudError::Conflict(_))));
+        assert!(matches!(dav.put("a.json", b"three".to_vec(), Expect::Version(first.version.clone())).await, Err(CloudError::Conflict(_))));
+        assert!(matches!(dav.delete("gone.json", Expect::Version(second.version.clone())).await, Err(CloudError::Conflict(_))));
+        assert_eq!(dav.get("a.json").await.unwrap().unwrap().1, b"two".to_vec());
+
+        let puts: Vec<Received> = stub.received().into_iter().filter(|request| request.method == "PUT").collect();
+        assert_eq!(puts[0].header("if-none-match"), Some("*"));
+        assert_eq!(puts[1].header("if-match"), None);
+        assert_eq!(puts.last().unwrap().header("if-match"), Some(first.version.as_str()));
+    }
+
+    #[tokio::test]
+    async fn refuses_names_outside_the_folder() {
+        let (stub, _server, dav) = server();
+        for name in &["", "../a.json", ".hidden", "a/b.json"] {
+            assert!(matches!(dav.put(name, Vec::new(), Expect::Any).await, Err(CloudError::Invalid(_))));
+        }
+        assert!(stub.received().is_empty());
+    }
+}
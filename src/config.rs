@@ -1,3 +1,89 @@
 use egui::{Context, Vec2};
 use serde::{Serialize, Deserialize};
 use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use crate::app::AppConfig;
+
+// name the config was stored under before it moved next to the notes' config dir
+const LEGACY_CONFIG: &str = "Snow Window";
+
+/// Command line flags: `--data-dir <dir>` to keep the notes somewhere else for this
+/// launch and `--portable` to keep everything next to the executable.
+#[derive(Default, Debug)]
+pub struct Args {
+    pub data_dir: Option<PathBuf>,
+    pub portable: bool,
+}
+
+impl Args {
+    pub fn parse() -> Self {
+        let mut args = Args::default();
+        let mut flags = std::env::args().skip(1);
+        while let Some(flag) = flags.next() {
+            match flag.as_str() {
+                "--portable" => args.portable = true,
+                "--data-dir" => match flags.next() {
+                    Some(dir) => args.data_dir = Some(PathBuf::from(dir)),
+                    None => warn!("[Snow]: --data-dir needs a directory"),
+                },
+                _ => match flag.strip_prefix("--data-dir=") {
+                    Some(dir) => args.data_dir = Some(PathBuf::from(dir)),
+                    None => warn!("[Snow]: ignoring unknown argument '{}'", flag),
+                },
+            }
+        }
+        args
+    }
+}
+
+/// Loads the config from `path`, or from where older builds stored it if there is nothing yet.
+pub fn load_config(path: &Path) -> AppConfig {
+    if !path.exists() {
+        // confy would create the old file if it is not there
+        let legacy = legacy_config_path().filter(|legacy| legacy.exists());
+        if let Some(config) = legacy.and_then(|legacy| confy::load_path::<AppConfig>(legacy).ok()) {
+            info!("[Snow]: moving the config to '{}'", path.display());
+            if let Err(e) = store_config(path, &config) {
+                error!("[Snow]: could not store config: {}", e);
+            }
+            return config;
+        }
+    }
+    let config = confy::load_path(path).unwrap_or_else(|e| {
+        error!("[Snow]: could not load config from '{}': {}", path.display(), e);
+        AppConfig::default()
+    });
+    // older builds left it readable for everyone
+    if path.exists() {
+        if let Err(e) = make_private(path) {
+            warn!("[Snow]: could not restrict access to '{}': {}", path.display(), e);
+        }
+    }
+    config
+}
+
+// where `confy::load(LEGACY_CONFIG)` keeps its file
+fn legacy_config_path() -> Option<PathBuf> {
+    let project = directories::ProjectDirs::from("rs", "", LEGACY_CONFIG)?;
+    Some(project.config_dir().join(format!("{}.toml", LEGACY_CONFIG)))
+}
+
+/// Stores the config so that only the user can read it, it holds the logins of the sync backends.
+pub fn store_config(path: &Path, config: &AppConfig) -> Result<(), confy::ConfyError> {
+    make_private(path).map_err(confy::ConfyError::OpenConfigurationFileError)?;
+    confy::store_path(path, config.clone())
+}
+
+// creates the file as 0600, or takes the other permissions away from an existing one,
+// before confy writes anything secret into it
+fn make_private(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        std::fs::OpenOptions::new().write(true).create(true).truncate(false).mode(0o600).open(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
@@ -0,0 +1,325 @@
+//! Line based diffing of note texts.
+
+use std::collections::HashMap;
+
+/// One line of a diff between an old and a new text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffLine<'a> {
+    /// in both texts
+    Same(&'a str),
+    /// only in the new text
+    Added(&'a str),
+    /// only in the old text
+    Removed(&'a str),
+}
+
+/// Compares `old` and `new` line by line, based on their longest common subsequence.
+/// Removed lines are listed before the lines that replaced them.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    let mut diff = Vec::with_capacity(old.len().max(new.len()));
+    let (mut i, mut j) = (0, 0);
+    for (a, b) in lcs_pairs(&old, &new) {
+        diff.extend(old[i..a].iter().map(|line| DiffLine::Removed(line)));
+        diff.extend(new[j..b].iter().map(|line| DiffLine::Added(line)));
+        diff.push(DiffLine::Same(old[a]));
+        i = a + 1;
+        j = b + 1;
+    }
+    diff.extend(old[i..].iter().map(|line| DiffLine::Removed(line)));
+    diff.extend(new[j..].iter().map(|line| DiffLine::Added(line)));
+    diff
+}
+
+/// Combines two versions of a text without losing a line of either: every line of `ours`,
+/// with the lines that only `theirs` has put in where they are in `theirs`.
+pub fn union_merge(ours: &str, theirs: &str) -> String {
+    let lines: Vec<&str> = diff_lines(ours, theirs)
+        .into_iter()
+        .map(|line| match line {
+            DiffLine::Same(line) | DiffLine::Added(line) | DiffLine::Removed(line) => line,
+        })
+        .collect();
+    let mut merged = lines.join("\n");
+    if ours.ends_with('\n') || theirs.ends_with('\n') {
+        merged.push('\n');
+    }
+    merged
+}
+
+/// Three-way merge of two texts that were both changed from `base`, line by line. Every
+/// stretch that only one side changed takes that change, `None` if both changed the same
+/// stretch in different ways.
+pub fn merge3(base: &str, ours: &str, theirs: &str) -> Option<String> {
+    let base_lines: Vec<&str> = base.lines().collect();
+    let our_lines: Vec<&str> = ours.lines().collect();
+    let their_lines: Vec<&str> = theirs.lines().collect();
+
+    // lines of the base both sides still have, the stretches between them are merged
+    let to_ours: HashMap<usize, usize> = lcs_pairs(&base_lines, &our_lines).into_iter().collect();
+    let to_theirs: HashMap<usize, usize> = lcs_pairs(&base_lines, &their_lines).into_iter().collect();
+    let mut stable: Vec<(usize, usize, usize)> = (0..base_lines.len())
+        .filter_map(|b| Some((b, *to_ours.get(&b)?, *to_theirs.get(&b)?)))
+        .collect();
+    // the end of all three texts, so the last stretch is merged as well
+    stable.push((base_lines.len(), our_lines.len(), their_lines.len()));
+
+    let mut merged: Vec<&str> = Vec::new();
+    let (mut b, mut o, mut t) = (0, 0, 0);
+    for (next_b, next_o, next_t) in stable {
+        merged.extend(merge_stretch(&base_lines[b..next_b], &our_lines[o..next_o], &their_lines[t..next_t])?);
+        if let Some(line) = base_lines.get(next_b) {
+            merged.push(line);
+        }
+        b = next_b + 1;
+        o = next_o + 1;
+        t = next_t + 1;
+    }
+
+    let mut text = merged.join("\n");
+    // a trailing newline is merged like a line of its own
+    let newline = |text: &str| [text.ends_with('\n')];
+    if pick(&newline(base), &newline(ours), &newline(theirs)) == Some(&[true]) {
+        text.push('\n');
+    }
+    Some(text)
+}
+
+// a stretch between lines both sides kept. if both changed it but only replaced lines, e.g.
+// one side the first and the other the second line, it is merged line by line
+fn merge_stretch<'a>(base: &[&'a str], ours: &[&'a str], theirs: &[&'a str]) -> Option<Vec<&'a str>> {
+    if let Some(lines) = pick(base, ours, theirs) {
+        return Some(lines.to_vec());
+    }
+    if ours.len() != base.len() || theirs.len() != base.len() {
+        return None;
+    }
+    (0..base.len()).map(|i| Some(pick(&base[i..=i], &ours[i..=i], &theirs[i..=i])?[0])).collect()
+}
+
+// the side of a stretch that changed, `None` if both changed it differently
+fn pick<'a, T: PartialEq>(base: &'a [T], ours: &'a [T], theirs: &'a [T]) -> Option<&'a [T]> {
+    if ours == base || ours == theirs {
+        Some(theirs)
+    } else if theirs == base {
+        Some(ours)
+    } else {
+        None
+    }
+}
+
+/// Index pairs `(i, j)` with `a[i] == b[j]` forming a longest common subsequence, in order.
+pub(crate) fn lcs_pairs<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    // the common start and end are cheap to skip and usually most of a note, only the
+    // middle needs the table
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a_end, b_end) = (a.len() - suffix, b.len() - suffix);
+
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+    split_pairs(&a[prefix..a_end], &b[prefix..b_end], (prefix, prefix), TABLE_CELLS, &mut pairs);
+    pairs.extend((0..suffix).map(|k| (a_end + k, b_end + k)));
+    pairs
+}
+
+// the table is 4 bytes a cell, 16 MB at most
+const TABLE_CELLS: usize = 1 << 22;
+
+// Hirschberg: halves `a`, finds where in `b` the lcs crosses that line with two rows of
+// lengths and goes on with both sides, so the memory stays linear. Parts small enough
+// for `table_pairs` go there instead.
+fn split_pairs<T: PartialEq>(a: &[T], b: &[T], offset: (usize, usize), table_cells: usize, pairs: &mut Vec<(usize, usize)>) {
+    if a.is_empty() || b.is_empty() {
+        return;
+    }
+    if a.len().saturating_mul(b.len()) <= table_cells {
+        pairs.extend(table_pairs(a, b).into_iter().map(|(i, j)| (offset.0 + i, offset.1 + j)));
+        return;
+    }
+    if a.len() == 1 {
+        pairs.extend(b.iter().position(|y| *y == a[0]).map(|j| (offset.0, offset.1 + j)));
+        return;
+    }
+
+    let mid = a.len() / 2;
+    let front = front_lengths(&a[..mid], b);
+    let back = back_lengths(&a[mid..], b);
+    let split = (0..=b.len()).max_by_key(|&j| front[j] + back[j]).unwrap_or(0);
+    split_pairs(&a[..mid], &b[..split], offset, table_cells, pairs);
+    split_pairs(&a[mid..], &b[split..], (offset.0 + mid, offset.1 + split), table_cells, pairs);
+}
+
+// lengths[j] = length of the lcs of `a` and b[..j]
+fn front_lengths<T: PartialEq>(a: &[T], b: &[T]) -> Vec<u32> {
+    let mut lengths = vec![0u32; b.len() + 1];
+    for x in a {
+        let mut diagonal = 0;
+        for (j, y) in b.iter().enumerate() {
+            let above = lengths[j + 1];
+            lengths[j + 1] = if x == y { diagonal + 1 } else { above.max(lengths[j]) };
+            diagonal = above;
+        }
+    }
+    lengths
+}
+
+// lengths[j] = length of the lcs of `a` and b[j..]
+fn back_lengths<T: PartialEq>(a: &[T], b: &[T]) -> Vec<u32> {
+    let mut lengths = vec![0u32; b.len() + 1];
+    for x in a.iter().rev() {
+        let mut diagonal = 0;
+        for j in (0..b.len()).rev() {
+            let above = lengths[j];
+            lengths[j] = if *x == b[j] { diagonal + 1 } else { above.max(lengths[j + 1]) };
+            diagonal = above;
+        }
+    }
+    lengths
+}
+
+// the quadratic part of `lcs_pairs`, for middles of up to `TABLE_CELLS`
+fn table_pairs<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    // lengths[i][j] = length of the lcs of a[i..] and b[j..]
+    let mut lengths = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = Vec::with_capacity(lengths[0][0] as usize);
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_lines() {
+        let diff = diff_lines("a\nb\nc\nd", "a\nB\nc\nd\ne");
+        assert_eq!(diff, vec![
+            DiffLine::Same("a"),
+            DiffLine::Removed("b"),
+            DiffLine::Added("B"),
+            DiffLine::Same("c"),
+            DiffLine::Same("d"),
+            DiffLine::Added("e"),
+        ]);
+        assert!(diff_lines("", "").is_empty());
+    }
+
+    #[test]
+    fn skips_the_common_start_and_end() {
+        let a = ["x", "a", "b", "c", "y"];
+        let b = ["x", "b", "c", "d", "y"];
+        assert_eq!(lcs_pairs(&a, &b), vec![(0, 0), (2, 1), (3, 2), (4, 4)]);
+        assert_eq!(table_pairs(&a, &b), lcs_pairs(&a, &b));
+        assert_eq!(lcs_pairs(&a, &a), (0..5).map(|i| (i, i)).collect::<Vec<_>>());
+        assert_eq!(lcs_pairs(&a[..0], &b), Vec::new());
+
+        // only the middle ends up in the table, a long note with one changed line stays cheap
+        let long: Vec<String> = (0..100_000).map(|i| i.to_string()).collect();
+        let mut changed = long.clone();
+        changed[50_000] = "changed".to_owned();
+        assert_eq!(lcs_pairs(&long, &changed).len(), long.len() - 1);
+    }
+
+    // made up lines out of a small alphabet, so there are many subsequences to choose from
+    fn lines(seed: u64, len: usize) -> Vec<u64> {
+        let mut state = seed;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) % 8
+        }).collect()
+    }
+
+    fn assert_common(a: &[u64], b: &[u64], pairs: &[(usize, usize)]) {
+        assert!(pairs.iter().all(|&(i, j)| a[i] == b[j]));
+        assert!(pairs.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+    }
+
+    #[test]
+    fn splitting_finds_as_long_a_subsequence_as_the_table() {
+        for seed in 0..20 {
+            let (a, b) = (lines(seed, 40 + seed as usize), lines(seed + 100, 60));
+            let table = table_pairs(&a, &b);
+            for cells in [0, 1, 50, 500] {
+                let mut pairs = Vec::new();
+                split_pairs(&a, &b, (0, 0), cells, &mut pairs);
+                assert_common(&a, &b, &pairs);
+                assert_eq!(pairs.len(), table.len(), "seed {} with {} cells", seed, cells);
+            }
+        }
+    }
+
+    #[test]
+    fn long_rewrites_do_not_need_the_whole_table() {
+        // every third line rewritten, first and last too, so the whole 36 million cells
+        // would end up in the table, 144 MB of it
+        let a = lines(1, 6000);
+        let b: Vec<u64> = a.iter().enumerate().map(|(i, &x)| if i % 3 == 0 { 100 } else { x }).collect();
+        let pairs = lcs_pairs(&a, &b);
+        assert_common(&a, &b, &pairs);
+        assert_eq!(pairs.len(), 4000);
+    }
+
+    #[test]
+    fn merges_changes_to_different_lines() {
+        let base = "one\ntwo\nthree\nfour\nfive\n";
+        let ours = "zero\none\ntwo\nthree\nfour\nfive\n";
+        let theirs = "one\ntwo\nthree\nfour\nfive\nsix\n";
+        assert_eq!(merge3(base, ours, theirs).as_deref(), Some("zero\none\ntwo\nthree\nfour\nfive\nsix\n"));
+
+        // next to each other, but each side only replaced its own line
+        let ours = "one\nTWO\nthree\nfour\nfive\n";
+        let theirs = "one\ntwo\nTHREE\nfour\nfive\n";
+        assert_eq!(merge3(base, ours, theirs).as_deref(), Some("one\nTWO\nTHREE\nfour\nfive\n"));
+
+        // one side removed a line, the other changed another
+        let ours = "one\nthree\nfour\nfive\n";
+        let theirs = "one\ntwo\nthree\nfour\nFIVE\n";
+        assert_eq!(merge3(base, ours, theirs).as_deref(), Some("one\nthree\nfour\nFIVE\n"));
+    }
+
+    #[test]
+    fn the_same_change_on_both_sides_is_no_conflict() {
+        let base = "one\ntwo";
+        assert_eq!(merge3(base, "one\n2", "one\n2").as_deref(), Some("one\n2"));
+        assert_eq!(merge3(base, base, "one\ntwo\n").as_deref(), Some("one\ntwo\n"));
+        assert_eq!(merge3(base, "one\ntwo\n", base).as_deref(), Some("one\ntwo\n"));
+    }
+
+    #[test]
+    fn overlapping_changes_conflict() {
+        let base = "one\ntwo\nthree\n";
+        assert_eq!(merge3(base, "one\nours\nthree\n", "one\ntheirs\nthree\n"), None);
+        // one side changed a line the other removed
+        assert_eq!(merge3(base, "one\nours\nthree\n", "one\nthree\n"), None);
+        // both added something different at the same place
+        assert_eq!(merge3(base, "one\ntwo\nthree\nours\n", "one\ntwo\nthree\ntheirs\n"), None);
+    }
+
+    #[test]
+    fn union_keeps_every_line() {
+        assert_eq!(union_merge("a\nb\nc\n", "a\nx\nc"), "a\nb\nx\nc\n");
+    }
+}
@@ -0,0 +1,294 @@
+//! Undo and redo of note edits.
+//!
+//! Every change to a note is recorded as an [`Edit`] on that note's own stack, so undoing
+//! in one note never touches another one. Edits of the same field that follow each other
+//! quickly (typing, dragging the color picker) are merged into a single step.
+
+use crate::{Note, NoteId, Notebook};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// edits of the same field closer together than this become one undo step
+const COALESCE: Duration = Duration::from_millis(1000);
+
+/// A single undoable change to a note.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    Title { old: String, new: String },
+    Text { old: String, new: String },
+    Color { old: [u8; 3], new: [u8; 3] },
+    /// the note was moved to the trash
+    Delete,
+}
+
+impl Edit {
+    // merges `next` into this edit if both change the same field
+    fn merge(&mut self, next: &Edit) -> bool {
+        match (self, next) {
+            (Edit::Title { new, .. }, Edit::Title { new: next, .. })
+            | (Edit::Text { new, .. }, Edit::Text { new: next, .. }) => {
+                *new = next.clone();
+                true
+            }
+            (Edit::Color { new, .. }, Edit::Color { new: next, .. }) => {
+                *new = *next;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn apply(&self, notebook: &mut Notebook, id: NoteId, undo: bool) -> bool {
+        match self {
+            Edit::Delete if undo => notebook.restore(id),
+            Edit::Delete => notebook.delete(id),
+            edit => notebook.update(id, |note| match edit {
+                Edit::Title { old, new } => note.set_title(if undo { old } else { new }.as_str()),
+                Edit::Text { old, new } => note.set_text(if undo { old } else { new }.as_str()),
+                Edit::Color { old, new } => note.set_color(if undo { *old } else { *new }),
+                Edit::Delete => unreachable!(),
+            }),
+        }
+    }
+}
+
+/// The title, text and color of a note before it was edited, see [`History::record_changes`].
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    title: String,
+    text: String,
+    color: [u8; 3],
+}
+
+impl Snapshot {
+    pub fn of(note: &Note) -> Self {
+        Snapshot { title: note.title.clone(), text: note.text.clone(), color: note.color }
+    }
+}
+
+#[derive(Default)]
+struct Stack {
+    // with the time the edit was last changed, `None` once it must not be merged into anymore
+    undo: Vec<(Edit, Option<Instant>)>,
+    redo: Vec<Edit>,
+}
+
+/// Undo and redo stacks for every note that was edited since the app started.
+#[derive(Default)]
+pub struct History {
+    notes: HashMap<NoteId, Stack>,
+    // the note that was edited, undone or redone last
+    last: Option<NoteId>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History::default()
+    }
+
+    /// Records `edit` as done on the note `id`, which drops anything that could be redone.
+    pub fn record(&mut self, id: NoteId, edit: Edit) {
+        let stack = self.notes.entry(id).or_default();
+        stack.redo.clear();
+        let now = Instant::now();
+        if let Some((last, Some(at))) = stack.undo.last_mut() {
+            if now.duration_since(*at) < COALESCE && last.merge(&edit) {
+                *at = now;
+                self.last = Some(id);
+                return;
+            }
+        }
+        stack.undo.push((edit, Some(now)));
+        self.last = Some(id);
+    }
+
+    /// Records whatever changed between `before` and the current state of `note`.
+    pub fn record_changes(&mut self, before: &Snapshot, note: &Note) {
+        if before.title != note.title {
+            self.record(note.id, Edit::Title { old: before.title.clone(), new: note.title.clone() });
+        }
+        if before.text != note.text {
+            self.record(note.id, Edit::Text { old: before.text.clone(), new: note.text.clone() });
+        }
+        if before.color != note.color {
+            self.record(note.id, Edit::Color { old: before.color, new: note.color });
+        }
+    }
+
+    /// Reverts the latest edit of the note. Returns the edit if there was one.
+    pub fn undo(&mut self, notebook: &mut Notebook, id: NoteId) -> Option<Edit> {
+        let stack = self.notes.get_mut(&id)?;
+        let (edit, _) = stack.undo.pop()?;
+        edit.apply(notebook, id, true);
+        stack.redo.push(edit.clone());
+        self.last = Some(id);
+        Some(edit)
+    }
+
+    /// Applies the latest undone edit of the note again.
+    pub fn redo(&mut self, notebook: &mut Notebook, id: NoteId) -> Option<Edit> {
+        let stack = self.notes.get_mut(&id)?;
+        let edit = stack.redo.pop()?;
+        edit.apply(notebook, id, false);
+        // never merged with later edits, otherwise it could not be undone on its own
+        stack.undo.push((edit.clone(), None));
+        self.last = Some(id);
+        Some(edit)
+    }
+
+    pub fn can_undo(&self, id: NoteId) -> bool {
+        self.notes.get(&id).is_some_and(|stack| !stack.undo.is_empty())
+    }
+
+    pub fn can_redo(&self, id: NoteId) -> bool {
+        self.notes.get(&id).is_some_and(|stack| !stack.redo.is_empty())
+    }
+
+    /// The note that was edited last, e.g. to undo a delete once its editor is closed.
+    pub fn last(&self) -> Option<NoteId> {
+        self.last
+    }
+
+    /// Drops the history of a note, e.g. once it was purged.
+    pub fn forget(&mut self, id: NoteId) {
+        self.notes.remove(&id);
+        if self.last == Some(id) {
+            self.last = None;
+        }
+    }
+
+    /// Drops everything, e.g. after the notes were reloaded from somewhere else.
+    pub fn clear(&mut self) {
+        self.notes.clear();
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notebook() -> (Notebook, NoteId) {
+        let note = Note::new(NoteId::new(), "a".to_string(), "title".to_string(), [0, 0, 0]);
+        let id = note.id;
+        (Notebook::new(vec![note]), id)
+    }
+
+    // changes the text of the note and records it like the editor does
+    fn type_text(history: &mut History, notebook: &mut Notebook, id: NoteId, text: &str) {
+        let before = Snapshot::of(notebook.get(id).unwrap());
+        notebook.update(id, |note| note.set_text(text));
+        history.record_changes(&before, notebook.get(id).unwrap());
+    }
+
+    fn text(notebook: &Notebook, id: NoteId) -> &str {
+        &notebook.get(id).unwrap().text
+    }
+
+    #[test]
+    fn typing_is_one_step() {
+        let (mut notebook, id) = notebook();
+        let mut history = History::new();
+        type_text(&mut history, &mut notebook, id, "ab");
+        type_text(&mut history, &mut notebook, id, "abc");
+        type_text(&mut history, &mut notebook, id, "abcd");
+
+        assert_eq!(history.undo(&mut notebook, id), Some(Edit::Text { old: "a".to_string(), new: "abcd".to_string() }));
+        assert_eq!(text(&notebook, id), "a");
+        assert!(!history.can_undo(id));
+    }
+
+    #[test]
+    fn different_fields_are_separate_steps() {
+        let (mut notebook, id) = notebook();
+        let mut history = History::new();
+        type_text(&mut history, &mut notebook, id, "ab");
+        let before = Snapshot::of(notebook.get(id).unwrap());
+        notebook.update(id, |note| note.set_color([1, 2, 3]));
+        history.record_changes(&before, notebook.get(id).unwrap());
+        type_text(&mut history, &mut notebook, id, "abc");
+
+        history.undo(&mut notebook, id);
+        assert_eq!(text(&notebook, id), "ab");
+        history.undo(&mut notebook, id);
+        assert_eq!(notebook.get(id).unwrap().color, [0, 0, 0]);
+        history.undo(&mut notebook, id);
+        assert_eq!(text(&notebook, id), "a");
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let (mut notebook, id) = notebook();
+        let mut history = History::new();
+        type_text(&mut history, &mut notebook, id, "ab");
+
+        history.undo(&mut notebook, id);
+        assert!(history.can_redo(id));
+        assert_eq!(history.redo(&mut notebook, id), Some(Edit::Text { old: "a".to_string(), new: "ab".to_string() }));
+        assert_eq!(text(&notebook, id), "ab");
+        assert!(!history.can_redo(id));
+        assert!(history.redo(&mut notebook, id).is_none());
+    }
+
+    #[test]
+    fn redone_edits_are_not_merged_into() {
+        let (mut notebook, id) = notebook();
+        let mut history = History::new();
+        type_text(&mut history, &mut notebook, id, "ab");
+        history.undo(&mut notebook, id);
+        history.redo(&mut notebook, id);
+        type_text(&mut history, &mut notebook, id, "abc");
+
+        history.undo(&mut notebook, id);
+        assert_eq!(text(&notebook, id), "ab");
+        history.undo(&mut notebook, id);
+        assert_eq!(text(&notebook, id), "a");
+    }
+
+    #[test]
+    fn a_new_edit_drops_the_redo_stack() {
+        let (mut notebook, id) = notebook();
+        let mut history = History::new();
+        type_text(&mut history, &mut notebook, id, "ab");
+        history.undo(&mut notebook, id);
+        type_text(&mut history, &mut notebook, id, "ax");
+        assert!(!history.can_redo(id));
+    }
+
+    #[test]
+    fn notes_have_their_own_stacks() {
+        let (mut notebook, a) = notebook();
+        let b = notebook.create().id;
+        let mut history = History::new();
+        type_text(&mut history, &mut notebook, a, "changed a");
+        type_text(&mut history, &mut notebook, b, "changed b");
+        assert_eq!(history.last(), Some(b));
+
+        history.undo(&mut notebook, a);
+        assert_eq!(text(&notebook, a), "a");
+        assert_eq!(text(&notebook, b), "changed b");
+    }
+
+    #[test]
+    fn undoing_a_delete_restores_the_note() {
+        let (mut notebook, id) = notebook();
+        let mut history = History::new();
+        notebook.delete(id);
+        history.record(id, Edit::Delete);
+
+        history.undo(&mut notebook, id);
+        assert!(!notebook.get(id).unwrap().is_deleted());
+        history.redo(&mut notebook, id);
+        assert!(notebook.get(id).unwrap().is_deleted());
+    }
+
+    #[test]
+    fn forget_drops_the_stacks() {
+        let (mut notebook, id) = notebook();
+        let mut history = History::new();
+        type_text(&mut history, &mut notebook, id, "ab");
+        history.forget(id);
+        assert!(!history.can_undo(id));
+        assert_eq!(history.last(), None);
+    }
+}
@@ -2,6 +2,8 @@
 use crate::app::SnowApp;
 use eframe::egui::{Vec2, Visuals, Color32};
 use reqwest;
+use snow_treading::storage::{self, Instance, Location};
+use crate::config::Args;
 
 mod app;
 mod note;
@@ -17,7 +19,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>>{
     pretty_env_logger::init();
 
     info!("Hello, world!");
-    let app = SnowApp::new();
+
+    // the config decides where the notes live, so it is read before anything else
+    let args = Args::parse();
+    let (config_file, _) = Location::config_file(args.portable)?;
+    let config = config::load_config(&config_file);
+    let location = Location::resolve(args.data_dir, config.data_dir.clone(), args.portable)?;
+    info!("[Snow]: keeping notes in '{}' ({:?})", location.data_dir.display(), location.source);
+    storage::set_data_dir(&location.data_dir);
+
+    // only one copy of the app may write the notes at a time
+    let instance = match Instance::acquire() {
+        Ok(Instance::Forwarded) => return Ok(()),
+        Ok(instance) => Some(instance),
+        Err(e) => {
+            error!("[Snow]: could not lock the data dir, other copies of the app are not noticed: {}", e);
+            None
+        }
+    };
+
+    let app = SnowApp::new(config, location, instance);
     let mut native_options = eframe::NativeOptions::default();
     native_options.initial_window_size = Some(Vec2::new(750., 760.));
     native_options.min_window_size = Some(Vec2::new(750., 440.));
@@ -0,0 +1,7 @@
+mod note;
+mod notebook;
+mod revision;
+
+pub use note::{Note, NoteId};
+pub use notebook::{repair_ids, Notebook, SortKey};
+pub use revision::Revision;
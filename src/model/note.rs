@@ -0,0 +1,248 @@
+use super::Revision;
+use crate::diff::merge3;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use ulid::Ulid;
+
+/// Identifies a note across saves, storage backends and machines.
+/// Stored as the 26 character string form of a [ULID](https://github.com/ulid/spec).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NoteId(Ulid);
+
+impl NoteId {
+    pub fn new() -> Self {
+        NoteId(Ulid::new())
+    }
+
+    /// Maps an id from before ULIDs were used (a random `i32`) to a ULID.
+    /// The mapping is deterministic, so every copy of a legacy note ends up
+    /// with the same id no matter when or where it gets migrated.
+    pub fn from_legacy(id: i32) -> Self {
+        NoteId(Ulid::from_parts(0, id as u32 as u128))
+    }
+}
+
+impl From<Ulid> for NoteId {
+    fn from(id: Ulid) -> Self {
+        NoteId(id)
+    }
+}
+
+impl Default for NoteId {
+    fn default() -> Self {
+        NoteId::new()
+    }
+}
+
+impl fmt::Display for NoteId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for NoteId {
+    type Err = ulid::DecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ulid::from_str(s).map(NoteId)
+    }
+}
+
+impl Serialize for NoteId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0)
+    }
+}
+
+// legacy integer ids are accepted as well, so hand edited or old files keep working
+impl<'de> Deserialize<'de> for NoteId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Ulid(String),
+            Legacy(i32),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Ulid(id) => id.parse().map_err(serde::de::Error::custom),
+            Raw::Legacy(id) => Ok(NoteId::from_legacy(id)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Note {
+    pub id: NoteId,
+    pub title: String,
+    pub text: String,
+    pub created: DateTime<Utc>,
+    /// last change of the title, text or color
+    pub modified: DateTime<Utc>,
+    /// rgb
+    pub color: [u8; 3],
+    /// set while the note is in the trash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<DateTime<Utc>>,
+    /// saved states of the note, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub revisions: Vec<Revision>,
+}
+
+impl Note {
+    /// What the title of a [conflicted copy](Note::conflicted_copy) ends with.
+    pub const CONFLICTED_COPY: &'static str = " (conflicted copy)";
+
+    pub fn new(id: NoteId, text: String, title: String, color: [u8; 3]) -> Self {
+        let now = Utc::now();
+        Note {
+            id,
+            title,
+            text,
+            created: now,
+            modified: now,
+            color,
+            deleted: None,
+            revisions: Vec::new(),
+        }
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted.is_some()
+    }
+
+    /// Marks the note as modified right now.
+    pub fn touch(&mut self) {
+        self.modified = Utc::now();
+    }
+
+    /// Whether title, text and color are the same, timestamps are not compared.
+    pub fn same_content(&self, other: &Note) -> bool {
+        self.title == other.title && self.text == other.text && self.color == other.color
+    }
+
+    pub fn set_title(&mut self, title: impl Into<String>) {
+        let title = title.into();
+        if self.title != title {
+            self.title = title;
+            self.touch();
+        }
+    }
+
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        if self.text != text {
+            self.text = text;
+            self.touch();
+        }
+    }
+
+    pub fn set_color(&mut self, color: [u8; 3]) {
+        if self.color != color {
+            self.color = color;
+            self.touch();
+        }
+    }
+
+    /// Combines the changes made to this note and to `theirs` since both were `base`: the
+    /// text is merged line by line, title, color and the trash state take whichever side
+    /// changed them. `None` if both sides changed the same thing differently.
+    pub fn merge(&self, base: &Note, theirs: &Note) -> Option<Note> {
+        fn field<T: PartialEq + Clone>(base: &T, ours: &T, theirs: &T) -> Option<T> {
+            if ours == base || ours == theirs {
+                Some(theirs.clone())
+            } else if theirs == base {
+                Some(ours.clone())
+            } else {
+                None
+            }
+        }
+
+        Some(Note {
+            title: field(&base.title, &self.title, &theirs.title)?,
+            text: merge3(&base.text, &self.text, &theirs.text)?,
+            color: field(&base.color, &self.color, &theirs.color)?,
+            // editing a note on one side and trashing it on the other keeps the edit in the trash
+            deleted: field(&base.deleted, &self.deleted, &theirs.deleted).unwrap_or(self.deleted.or(theirs.deleted)),
+            modified: self.modified.max(theirs.modified),
+            ..self.clone()
+        })
+    }
+
+    /// A new note with the content of this one, for a version that could not be merged.
+    pub fn conflicted_copy(&self) -> Note {
+        Note {
+            id: NoteId::new(),
+            title: format!("{}{}", self.title, Note::CONFLICTED_COPY),
+            revisions: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// A short single line preview of the text, at most `chars` characters long.
+    pub fn preview(&self, chars: usize) -> String {
+        self.text.chars().filter(|c| *c != '\n').take(chars).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(text: &str) -> Note {
+        Note::new(NoteId::new(), text.to_owned(), "Groceries".to_owned(), [0, 0, 0])
+    }
+
+    #[test]
+    fn merges_edits_to_different_parts() {
+        let base = note("milk\neggs\n");
+        let mut ours = base.clone();
+        ours.set_text("milk\neggs\nbread\n");
+        let mut theirs = base.clone();
+        theirs.set_text("oat milk\neggs\n");
+        theirs.set_color([255, 0, 0]);
+
+        let merged = ours.merge(&base, &theirs).unwrap();
+        assert_eq!(merged.text, "oat milk\neggs\nbread\n");
+        assert_eq!((merged.id, merged.color), (base.id, [255, 0, 0]));
+        assert_eq!(merged.modified, ours.modified.max(theirs.modified));
+    }
+
+    #[test]
+    fn conflicting_edits_end_up_in_a_copy() {
+        let base = note("milk\n");
+        let mut ours = base.clone();
+        ours.set_text("oat milk\n");
+        let mut theirs = base.clone();
+        theirs.set_text("soy milk\n");
+        assert!(ours.merge(&base, &theirs).is_none());
+
+        let copy = ours.conflicted_copy();
+        assert_ne!(copy.id, ours.id);
+        assert_eq!(copy.title, "Groceries (conflicted copy)");
+        assert_eq!(copy.text, "oat milk\n");
+        assert!(copy.revisions.is_empty());
+
+        // titles conflict like lines do
+        let mut retitled = base.clone();
+        retitled.set_title("Shopping");
+        let mut renamed = base.clone();
+        renamed.set_title("Errands");
+        assert!(retitled.merge(&base, &renamed).is_none());
+    }
+
+    #[test]
+    fn an_edit_survives_the_trash() {
+        let base = note("milk\n");
+        let mut ours = base.clone();
+        ours.set_text("oat milk\n");
+        let mut theirs = base.clone();
+        theirs.deleted = Some(Utc::now());
+
+        let merged = ours.merge(&base, &theirs).unwrap();
+        assert_eq!(merged.text, "oat milk\n");
+        assert_eq!(merged.deleted, theirs.deleted);
+    }
+}
@@ -0,0 +1,421 @@
+use super::{Note, NoteId};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
+
+/// What the notes are ordered by when listed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Modified,
+    Created,
+    Title,
+    Color,
+}
+
+impl SortKey {
+    pub const ALL: [SortKey; 4] = [SortKey::Modified, SortKey::Created, SortKey::Title, SortKey::Color];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SortKey::Modified => "Last edited",
+            SortKey::Created => "Created",
+            SortKey::Title => "Title A-Z",
+            SortKey::Color => "Color",
+        }
+    }
+
+    /// Compares two notes by this key only.
+    pub fn compare(&self, a: &Note, b: &Note) -> Ordering {
+        match self {
+            SortKey::Modified => a.modified.cmp(&b.modified),
+            SortKey::Created => a.created.cmp(&b.created),
+            SortKey::Title => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+            SortKey::Color => hue(a.color).partial_cmp(&hue(b.color)).unwrap_or(Ordering::Equal)
+                .then_with(|| a.color.cmp(&b.color)),
+        }
+    }
+}
+
+// hue in degrees, so similar colors end up next to each other
+fn hue(color: [u8; 3]) -> f32 {
+    let [r, g, b] = color.map(|channel| channel as f32 / 255.);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = if delta == 0. {
+        0.
+    } else if max == r {
+        60. * (((g - b) / delta) % 6.)
+    } else if max == g {
+        60. * ((b - r) / delta + 2.)
+    } else {
+        60. * ((r - g) / delta + 4.)
+    };
+    if hue < 0. { hue + 360. } else { hue }
+}
+
+/// The collection of notes the app works on. Only knows about notes, nothing about
+/// how they are displayed or where they are stored.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Notebook {
+    notes: Vec<Note>,
+    // every note as it was last loaded from or written to the store
+    saved: HashMap<NoteId, Note>,
+}
+
+impl Notebook {
+    /// The notes are taken to be the same as in the store.
+    pub fn new(notes: Vec<Note>) -> Self {
+        let saved = notes.iter().map(|note| (note.id, note.clone())).collect();
+        Notebook { notes, saved }
+    }
+
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    /// Mutable access to the notes themselves, the collection can only be changed through
+    /// [`create`](Self::create), [`insert`](Self::insert) and [`purge`](Self::purge).
+    pub fn notes_mut(&mut self) -> &mut [Note] {
+        &mut self.notes
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Note> {
+        self.notes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.notes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    pub fn get(&self, id: NoteId) -> Option<&Note> {
+        self.notes.iter().find(|note| note.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: NoteId) -> Option<&mut Note> {
+        self.notes.iter_mut().find(|note| note.id == id)
+    }
+
+    pub fn position(&self, id: NoteId) -> Option<usize> {
+        self.notes.iter().position(|note| note.id == id)
+    }
+
+    /// Remembers the current state of the note as the one in the store.
+    pub fn mark_saved(&mut self, id: NoteId) {
+        if let Some(note) = self.get(id) {
+            let note = note.clone();
+            self.saved.insert(id, note);
+        }
+    }
+
+    /// Remembers `note` as the state in the store, the note in the notebook might have
+    /// changed again since it was written.
+    pub fn mark_saved_as(&mut self, note: Note) {
+        self.saved.insert(note.id, note);
+    }
+
+    pub fn mark_all_saved(&mut self) {
+        self.saved = self.notes.iter().map(|note| (note.id, note.clone())).collect();
+    }
+
+    /// The note as it was last loaded from or written to the store.
+    pub fn saved(&self, id: NoteId) -> Option<&Note> {
+        self.saved.get(&id)
+    }
+
+    /// Whether the note differs from its last saved state, notes that were never saved always do.
+    pub fn is_dirty(&self, id: NoteId) -> bool {
+        match (self.get(id), self.saved.get(&id)) {
+            (Some(note), Some(saved)) => !note.same_content(saved),
+            (Some(_), None) => true,
+            _ => false,
+        }
+    }
+
+    /// Ids of all notes with unsaved changes.
+    pub fn dirty(&self) -> Vec<NoteId> {
+        self.notes.iter().map(|note| note.id).filter(|id| self.is_dirty(*id)).collect()
+    }
+
+    /// Throws away the unsaved changes of a note, a note that was never saved is removed.
+    pub fn discard(&mut self, id: NoteId) {
+        match self.saved.get(&id) {
+            Some(saved) => {
+                let saved = saved.clone();
+                self.insert(saved);
+            }
+            None => {
+                self.notes.retain(|note| note.id != id);
+            }
+        }
+    }
+
+    /// Adds a new, empty note and returns it.
+    pub fn create(&mut self) -> &mut Note {
+        self.notes.push(Note::new(NoteId::new(), String::new(), String::new(), [0, 0, 0]));
+        self.notes.last_mut().unwrap()
+    }
+
+    /// See [`repair_ids`].
+    pub fn repair_ids(&mut self) -> Vec<NoteId> {
+        repair_ids(&mut self.notes)
+    }
+
+    /// Adds `note`, replacing the note with the same id if there is one.
+    pub fn insert(&mut self, note: Note) {
+        match self.get_mut(note.id) {
+            Some(existing) => *existing = note,
+            None => self.notes.push(note),
+        }
+    }
+
+    /// Applies `change` to the note with the given id and bumps its modification time
+    /// if the content actually changed. Returns whether the note exists.
+    pub fn update(&mut self, id: NoteId, change: impl FnOnce(&mut Note)) -> bool {
+        match self.get_mut(id) {
+            Some(note) => {
+                let before = note.clone();
+                change(note);
+                if !note.same_content(&before) && note.modified == before.modified {
+                    note.touch();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the note to the trash. Returns whether it was found and not trashed already.
+    pub fn delete(&mut self, id: NoteId) -> bool {
+        match self.get_mut(id) {
+            Some(note) if !note.is_deleted() => {
+                note.deleted = Some(Utc::now());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Takes the note back out of the trash.
+    pub fn restore(&mut self, id: NoteId) -> bool {
+        match self.get_mut(id) {
+            Some(note) if note.is_deleted() => {
+                note.deleted = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes the note for good, trashed or not.
+    pub fn purge(&mut self, id: NoteId) -> Option<Note> {
+        let index = self.position(id)?;
+        self.saved.remove(&id);
+        Some(self.notes.remove(index))
+    }
+
+    /// Purges every note that has been in the trash for longer than `retention`.
+    pub fn purge_expired(&mut self, retention: Duration) -> Vec<NoteId> {
+        let cutoff = Utc::now() - retention;
+        let expired: Vec<NoteId> = self.trashed()
+            .filter(|note| note.deleted.is_some_and(|deleted| deleted < cutoff))
+            .map(|note| note.id)
+            .collect();
+        self.notes.retain(|note| !expired.contains(&note.id));
+        self.saved.retain(|id, _| !expired.contains(id));
+        expired
+    }
+
+    /// Notes that are not in the trash.
+    pub fn live(&self) -> impl Iterator<Item = &Note> {
+        self.notes.iter().filter(|note| !note.is_deleted())
+    }
+
+    /// Notes in the trash, most recently deleted first.
+    pub fn trashed(&self) -> impl Iterator<Item = &Note> {
+        let mut trashed: Vec<&Note> = self.notes.iter().filter(|note| note.is_deleted()).collect();
+        trashed.sort_by_key(|note| Reverse(note.deleted));
+        trashed.into_iter()
+    }
+
+    /// Stable sort, notes that compare equal keep their relative order.
+    pub fn sort_by(&mut self, compare: impl FnMut(&Note, &Note) -> Ordering) {
+        self.notes.sort_by(compare);
+    }
+
+    /// Indices into [`notes`](Self::notes) of all notes outside the trash in the given order,
+    /// the notes themselves are not moved. Ties are broken by id, so the order is the same every time.
+    pub fn sorted_indices(&self, key: SortKey, ascending: bool) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..self.notes.len()).filter(|&i| !self.notes[i].is_deleted()).collect();
+        indices.sort_by(|&a, &b| {
+            let (a, b) = (&self.notes[a], &self.notes[b]);
+            let ordering = key.compare(a, b).then_with(|| a.id.cmp(&b.id));
+            if ascending { ordering } else { ordering.reverse() }
+        });
+        indices
+    }
+
+    pub fn into_notes(self) -> Vec<Note> {
+        self.notes
+    }
+}
+
+/// Gives every note whose id was already used by an earlier note a fresh id,
+/// e.g. after a file was copied around by hand. Returns the new ids.
+pub fn repair_ids(notes: &mut [Note]) -> Vec<NoteId> {
+    let mut seen = HashSet::with_capacity(notes.len());
+    let mut repaired = Vec::new();
+    for note in notes.iter_mut() {
+        if !seen.insert(note.id) {
+            let id = NoteId::new();
+            warn!("[Snow]: note id {} is used more than once, '{}' gets the new id {}", note.id, note.title, id);
+            note.id = id;
+            seen.insert(id);
+            repaired.push(id);
+        }
+    }
+    repaired
+}
+
+impl From<Vec<Note>> for Notebook {
+    fn from(notes: Vec<Note>) -> Self {
+        Notebook::new(notes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notebook() -> (Notebook, NoteId, NoteId) {
+        let a = Note::new(NoteId::new(), "first".to_string(), "a".to_string(), [1, 2, 3]);
+        let b = Note::new(NoteId::new(), "second".to_string(), "b".to_string(), [4, 5, 6]);
+        let (a_id, b_id) = (a.id, b.id);
+        (Notebook::new(vec![a, b]), a_id, b_id)
+    }
+
+    #[test]
+    fn loaded_notes_are_clean() {
+        let (notebook, ..) = notebook();
+        assert!(notebook.dirty().is_empty());
+    }
+
+    #[test]
+    fn changes_make_a_note_dirty_until_saved() {
+        let (mut notebook, a, b) = notebook();
+        assert!(notebook.update(a, |note| note.set_text("changed")));
+        assert_eq!(notebook.dirty(), vec![a]);
+        assert!(!notebook.is_dirty(b));
+
+        notebook.mark_saved(a);
+        assert!(notebook.dirty().is_empty());
+        assert_eq!(notebook.saved(a).unwrap().text, "changed");
+    }
+
+    #[test]
+    fn changing_back_is_not_dirty() {
+        let (mut notebook, a, _) = notebook();
+        notebook.update(a, |note| note.set_text("changed"));
+        notebook.update(a, |note| note.set_text("first"));
+        assert!(!notebook.is_dirty(a));
+    }
+
+    #[test]
+    fn saved_as_an_older_state_stays_dirty() {
+        let (mut notebook, a, _) = notebook();
+        notebook.update(a, |note| note.set_text("written"));
+        let written = notebook.get(a).unwrap().clone();
+        // typed on while the worker was writing
+        notebook.update(a, |note| note.set_text("written and more"));
+        notebook.mark_saved_as(written);
+        assert!(notebook.is_dirty(a));
+    }
+
+    #[test]
+    fn new_notes_are_dirty_and_discarding_removes_them() {
+        let (mut notebook, ..) = notebook();
+        let id = notebook.create().id;
+        assert!(notebook.is_dirty(id));
+
+        notebook.discard(id);
+        assert!(notebook.get(id).is_none());
+        assert_eq!(notebook.len(), 2);
+    }
+
+    #[test]
+    fn discard_goes_back_to_the_saved_state() {
+        let (mut notebook, a, _) = notebook();
+        notebook.update(a, |note| note.set_title("renamed"));
+        notebook.discard(a);
+        assert_eq!(notebook.get(a).unwrap().title, "a");
+        assert!(!notebook.is_dirty(a));
+    }
+
+    #[test]
+    fn trash_is_no_unsaved_change() {
+        let (mut notebook, a, b) = notebook();
+        // the trash state is written right away
+        notebook.delete(a);
+        assert!(!notebook.is_dirty(a));
+
+        notebook.purge(a);
+        assert!(notebook.saved(a).is_none());
+        assert!(!notebook.is_dirty(a));
+        notebook.delete(b);
+        notebook.get_mut(b).unwrap().deleted = Some(Utc::now() - Duration::days(31));
+        notebook.purge_expired(Duration::days(30));
+        assert!(notebook.saved(b).is_none());
+    }
+
+    #[test]
+    fn trash_and_restore() {
+        let (mut notebook, a, b) = notebook();
+        assert!(notebook.delete(a));
+        assert!(!notebook.delete(a));
+        assert_eq!(notebook.live().map(|note| note.id).collect::<Vec<_>>(), vec![b]);
+        assert_eq!(notebook.trashed().map(|note| note.id).collect::<Vec<_>>(), vec![a]);
+        assert!(notebook.sorted_indices(SortKey::Title, true).iter().all(|&i| notebook.notes()[i].id == b));
+
+        assert!(notebook.restore(a));
+        assert!(!notebook.restore(a));
+        assert_eq!(notebook.trashed().count(), 0);
+    }
+
+    #[test]
+    fn purge_forgets_the_note() {
+        let (mut notebook, a, b) = notebook();
+        notebook.delete(a);
+        assert_eq!(notebook.purge(a).map(|note| note.id), Some(a));
+        assert!(notebook.get(a).is_none());
+        assert!(notebook.purge(a).is_none());
+        assert_eq!(notebook.len(), 1);
+        assert!(notebook.get(b).is_some());
+    }
+
+    #[test]
+    fn purge_expired_keeps_recent_trash() {
+        let (mut notebook, a, b) = notebook();
+        notebook.delete(a);
+        notebook.delete(b);
+        notebook.get_mut(a).unwrap().deleted = Some(Utc::now() - Duration::days(31));
+
+        assert_eq!(notebook.purge_expired(Duration::days(30)), vec![a]);
+        assert!(notebook.get(a).is_none());
+        assert!(notebook.get(b).is_some());
+    }
+
+    #[test]
+    fn repair_ids_only_touches_duplicates() {
+        let note = Note::new(NoteId::new(), String::new(), "a".to_string(), [0, 0, 0]);
+        let mut notes = vec![note.clone(), note.clone()];
+        let repaired = repair_ids(&mut notes);
+        assert_eq!(notes[0].id, note.id);
+        assert_eq!(repaired, vec![notes[1].id]);
+        assert_ne!(notes[1].id, note.id);
+    }
+}
@@ -0,0 +1,60 @@
+use super::Note;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The content of a note at the time it was saved.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Revision {
+    pub at: DateTime<Utc>,
+    pub title: String,
+    pub text: String,
+    pub color: [u8; 3],
+}
+
+impl Revision {
+    pub fn of(note: &Note) -> Self {
+        Revision {
+            at: Utc::now(),
+            title: note.title.clone(),
+            text: note.text.clone(),
+            color: note.color,
+        }
+    }
+
+    fn same_content(&self, note: &Note) -> bool {
+        self.title == note.title && self.text == note.text && self.color == note.color
+    }
+}
+
+impl Note {
+    /// Records the current content as a new revision, unless it did not change since the last one.
+    /// Only the newest `keep` revisions are kept, `0` keeps all of them.
+    /// Returns whether a revision was added.
+    pub fn record_revision(&mut self, keep: usize) -> bool {
+        if self.revisions.last().is_some_and(|last| last.same_content(self)) {
+            return false;
+        }
+        self.revisions.push(Revision::of(self));
+        if keep > 0 && self.revisions.len() > keep {
+            let excess = self.revisions.len() - keep;
+            self.revisions.drain(..excess);
+        }
+        true
+    }
+
+    /// Goes back to the content of revision `index`. Unsaved changes are recorded first and the
+    /// rollback is recorded as a revision itself, so it can be undone the same way.
+    pub fn rollback(&mut self, index: usize, keep: usize) -> bool {
+        let revision = match self.revisions.get(index) {
+            Some(revision) => revision.clone(),
+            None => return false,
+        };
+        self.record_revision(keep);
+        self.title = revision.title;
+        self.text = revision.text;
+        self.color = revision.color;
+        self.touch();
+        self.record_revision(keep);
+        true
+    }
+}
@@ -1,53 +1,108 @@
-use chrono::Local;
 use eframe::egui::{Color32, Window, RichText, TextEdit, Button, TextStyle, Id};
 
 use serde::{Serialize, Deserialize};
 use egui::{Context, Vec2};
 use eframe::epi::egui::Layout;
-use snow_treading::save_file;
+use snow_treading::diff::{diff_lines, union_merge, DiffLine};
+use snow_treading::history::{Edit, History, Snapshot};
+use snow_treading::toast::Notifier;
+use snow_treading::storage::Worker;
+use snow_treading::{Note, NoteId, Notebook};
+use chrono::{DateTime, Local, Utc};
 use std::collections::HashMap;
 use std::ops::Index;
 use egui::text_edit::CursorRange;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+// ui state of the note editor, the notes themselves live in the `Notebook`
+#[derive(Clone, Debug, Default)]
 pub struct NoteWarp {
-    pub(crate) notes: Vec<Note>,
-    pub(crate) confirmation_window: (bool, String),
-    pub(crate) bool: bool,
+    pub(crate) notifier: Notifier,
+    pub(crate) editor_open: bool,
     pub(crate) closing_window: bool,
+    pub(crate) history_open: bool,
+    // revisions compared in the history panel, `None` means the latest revision and the current text
+    pub(crate) history_from: Option<usize>,
+    pub(crate) history_to: Option<usize>,
+    // versions of notes that were changed outside of the app while they had unsaved changes here
+    pub(crate) conflicts: HashMap<NoteId, Note>,
+    // notes a sync could not merge, the note with the other device's version -> the copy of this one's
+    pub(crate) copies: HashMap<NoteId, NoteId>,
+    pub(crate) compare_open: bool,
 }
 
+// the other version of the note in the editor
+enum Other {
+    // changed outside of the app, see `NoteWarp::conflicts`
+    Outside(Note),
+    // changed on two devices, see `NoteWarp::copies`
+    Synced { original: NoteId, copy: NoteId, mine: Note, theirs: Note },
+}
+
+// what to do about a note that was changed outside of the app
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Resolution {
+    KeepMine,
+    TakeTheirs,
+    Merge,
+    KeepBoth,
+}
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct Note {
-    pub id: i32,
-    pub title: String,
-    pub text: String,
-    pub date_last_edited: String,
-    color: [u8; 3],
+// answers to the unsaved changes prompt
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Unsaved {
+    Save,
+    Discard,
+    Cancel,
 }
 
-impl Note {
-    pub fn new(id: i32, text: String, title: String, color: [u8; 3]) -> Self {
-        Note {
-            id,
-            title,
-            text,
-            date_last_edited: Local::now().to_rfc2822(),
-            color,
+// the buttons of the unsaved changes prompt, returns the one that was clicked
+pub(crate) fn unsaved_buttons(ui: &mut egui::Ui) -> Option<Unsaved> {
+    let mut choice = None;
+    ui.horizontal(|ui| {
+        if ui.button(RichText::new("Save").strong()).clicked() {
+            choice = Some(Unsaved::Save);
         }
-    }
-    pub fn get_note_color(&self) -> Color32 {
-        Color32::from_rgb(self.color[0], self.color[1], self.color[2])
-    }
+        if ui.button("Discard").clicked() {
+            choice = Some(Unsaved::Discard);
+        }
+        if ui.button("Cancel").clicked() {
+            choice = Some(Unsaved::Cancel);
+        }
+    });
+    choice
+}
+
+// timestamps are stored in utc but shown in the users timezone
+pub(crate) fn local_time(time: &DateTime<Utc>) -> String {
+    time.with_timezone(&Local).format("%d.%m.%Y %H:%M").to_string()
+}
+
+// the color a note is drawn with
+pub(crate) fn note_color(note: &Note) -> Color32 {
+    Color32::from_rgb(note.color[0], note.color[1], note.color[2])
 }
 
+
 impl NoteWarp {
 
     // TODO: Character count for title and text
-    pub(crate) fn note_window(&mut self, ctx: &Context, index: usize) {
+    pub(crate) fn note_window(&mut self, ctx: &Context, notebook: &mut Notebook, id: NoteId, worker: &Worker, history: &mut History, revision_limit: usize) {
 
-        let title_len = self.notes[index].title.len() as f32;
+        let other = self.other_version(notebook, id);
+        let note = match notebook.get_mut(id) {
+            Some(note) => note,
+            None => {
+                self.editor_open = false;
+                return;
+            }
+        };
+        let title_len = note.title.len() as f32;
+        // compared against after the window to record the edits for undo
+        let before = Snapshot::of(note);
+        let dirty = notebook.is_dirty(id);
+        let mut delete = false;
+        let mut save = false;
+        let mut resolution = None;
 
         // add the popup window for note creation
         let window = Window::new("Edit Note")
@@ -58,31 +113,89 @@ impl NoteWarp {
         let m = window
             .show(ctx, |ui| {
                 // locking window width
-                ui.set_max_width(300.);
+                ui.set_max_width(if self.history_open || self.compare_open { 650. } else { 300. });
                 // padding
                 //ui.add_space(8.);
-                //TODO: Top Field, Asking to save before exit
                 egui::menu::bar(ui, |ui| {
                         ui.with_layout(Layout::right_to_left(), |ui| {
                             let mut close_btn = ui.add(Button::new(RichText::new("⛔")));
                             if close_btn.clicked() {
-                                self.bool = false;
+                                // ask first if there is something that would get lost
+                                if dirty {
+                                    self.closing_window = true;
+                                } else {
+                                    self.editor_open = false;
+                                }
+                            }
+                            let delete_btn = ui.add(Button::new(RichText::new("🗑")))
+                                .on_hover_text("Move to trash");
+                            if delete_btn.clicked() {
+                                delete = true;
+                            }
+                            let history_btn = ui.add(Button::new(RichText::new("🕑")))
+                                .on_hover_text("History");
+                            if history_btn.clicked() {
+                                self.history_open = !self.history_open;
                             }
                             });
                         });
 
                 ui.separator();
+                match &other {
+                    Some(Other::Outside(theirs)) => {
+                        ui.horizontal_wrapped(|ui| {
+                            ui.label(RichText::new("⚠ changed outside of the app").color(Color32::from_rgb(230, 160, 60)))
+                                .on_hover_text(format!("the other version was edited {}", local_time(&theirs.modified)));
+                            if ui.small_button("Keep mine").clicked() {
+                                resolution = Some(Resolution::KeepMine);
+                            }
+                            if ui.small_button("Take theirs").clicked() {
+                                resolution = Some(Resolution::TakeTheirs);
+                            }
+                            if ui.small_button("Merge").on_hover_text("Combines the changes of both versions").clicked() {
+                                resolution = Some(Resolution::Merge);
+                            }
+                            if ui.small_button("Compare").clicked() {
+                                self.compare_open = !self.compare_open;
+                            }
+                        });
+                        ui.separator();
+                    }
+                    Some(Other::Synced { theirs, .. }) => {
+                        ui.horizontal_wrapped(|ui| {
+                            ui.label(RichText::new("⚠ changed on two devices").color(Color32::from_rgb(230, 160, 60)))
+                                .on_hover_text(format!("the other device's version was edited {}", local_time(&theirs.modified)));
+                            if ui.small_button("Keep this device's").clicked() {
+                                resolution = Some(Resolution::KeepMine);
+                            }
+                            if ui.small_button("Keep the other's").clicked() {
+                                resolution = Some(Resolution::TakeTheirs);
+                            }
+                            if ui.small_button("Keep both").on_hover_text("Keeps the conflicted copy as a note of its own").clicked() {
+                                resolution = Some(Resolution::KeepBoth);
+                            }
+                            if ui.small_button("Compare").clicked() {
+                                self.compare_open = !self.compare_open;
+                            }
+                        });
+                        ui.separator();
+                    }
+                    None => {}
+                }
                 ui.add_space(5.);
                 // top line title edit widget TODO: Fix max characters!
                 ui.horizontal_top(|ui| {
                     ui.add_space(5.);
-                    let mut title_field = TextEdit::singleline(&mut self.notes[index].title)
+                    let mut title_field = TextEdit::singleline(&mut note.title)
                         .id(Id::new("text_file"))
                         .margin(Vec2::new(if title_len < 43. {130. - (title_len * 2.9)} else {8.}, 5.))
                         .font(TextStyle::Heading)
                         .show(ui);
+                    if title_field.response.changed() {
+                        note.touch();
+                    }
 
-                    // ui.text_edit_singleline(&mut self.notes[index].title).on_hover_text(RichText::new("Change Title"));
+                    // ui.text_edit_singleline(&mut note.title).on_hover_text(RichText::new("Change Title"));
                 });
                 ui.add_space(10.);
 
@@ -92,28 +205,53 @@ impl NoteWarp {
                     .show_inside(ui, |ui| {
                         ui.horizontal(|ui| {
                             ui.set_height(30.);
+                            ui.label(RichText::new(format!("edited {}", local_time(&note.modified))).small())
+                                .on_hover_text(format!("created {}", local_time(&note.created)));
+                            if dirty {
+                                ui.label(RichText::new("● unsaved").small().color(Color32::from_rgb(230, 160, 60)));
+                            }
                             ui.with_layout(Layout::right_to_left(), |ui| {
                                 // saving button
                                 let save_note_btn = ui.button(RichText::new("Save").strong().heading());
                                 if save_note_btn.clicked() {
-                                    save_file("data", &self.notes);
-                                    self.confirmation_window = (true, "saved!".to_string());
-                                    let mut count = 0;
-                                    while count <= 200 {
-                                        count += 1;
-                                    }
-                                    self.confirmation_window = (false, "".to_string())
+                                    save = true;
                                 }
                             });
                         });
                     });
 
+                if self.compare_open {
+                    if let Some(other) = &other {
+                        egui::SidePanel::right("note_compare")
+                            .resizable(false)
+                            .min_width(330.)
+                            .show_inside(ui, |ui| match other {
+                                Other::Outside(theirs) => compare_panel(ui, ("Mine", &*note), ("Theirs", theirs)),
+                                Other::Synced { mine, theirs, .. } => compare_panel(ui, ("This device", mine), ("Other device", theirs)),
+                            });
+                    }
+                }
+
+                if self.history_open {
+                    egui::SidePanel::right("note_history")
+                        .resizable(false)
+                        .min_width(330.)
+                        .show_inside(ui, |ui| {
+                            if self.history_panel(ui, note, revision_limit) {
+                                save = true;
+                            }
+                        });
+                }
+
                 // central panel containing text-edit, color picker etc...
                 egui::CentralPanel::default().show_inside(ui, |ui| {
 
                     // TODO: make this prettier
                     ui.horizontal(|ui| {
-                        let color_edit = ui.color_edit_button_srgb(&mut self.notes[index].color);
+                        let color_edit = ui.color_edit_button_srgb(&mut note.color);
+                        if color_edit.changed() {
+                            note.touch();
+                        }
                     });
 
                     let scroll_text = eframe::egui::ScrollArea::vertical()
@@ -121,10 +259,254 @@ impl NoteWarp {
                         .show(ui, |ui| {
                             let text_edit = ui.add_sized(
                                 ui.available_size(),
-                                TextEdit::multiline(&mut self.notes[index].text));
+                                TextEdit::multiline(&mut note.text));
+                            if text_edit.changed() {
+                                note.touch();
+                            }
                         });
 
                 });
         });
+
+        // the note is only borrowed by the window until here
+        match (resolution, other) {
+            (Some(resolution), Some(Other::Outside(_))) => self.resolve_conflict(notebook, id, resolution),
+            (Some(resolution), Some(Other::Synced { original, copy, .. })) => {
+                self.resolve_copy(notebook, original, copy, resolution, worker, history, revision_limit);
+                // the copy is in the trash now
+                if id == copy && resolution != Resolution::KeepBoth {
+                    self.editor_open = false;
+                }
+            }
+            _ => {}
+        }
+        if let Some(note) = notebook.get(id) {
+            history.record_changes(&before, note);
+        }
+        if save {
+            self.save_note(notebook, id, worker, revision_limit);
+        }
+        if delete && notebook.delete(id) {
+            history.record(id, Edit::Delete);
+            self.editor_open = false;
+            worker.upsert(notebook.get(id).unwrap().clone());
+        }
+
+        if self.closing_window && self.editor_open {
+            self.closing_prompt(ctx, notebook, id, worker, revision_limit);
+        }
     }
+
+    fn other_version(&self, notebook: &Notebook, id: NoteId) -> Option<Other> {
+        if let Some(theirs) = self.conflicts.get(&id) {
+            return Some(Other::Outside(theirs.clone()));
+        }
+        let (original, copy) = self.copies.iter()
+            .map(|(original, copy)| (*original, *copy))
+            .find(|(original, copy)| *original == id || *copy == id)?;
+        Some(Other::Synced { original, copy, mine: notebook.get(copy)?.clone(), theirs: notebook.get(original)?.clone() })
+    }
+
+    fn resolve_conflict(&mut self, notebook: &mut Notebook, id: NoteId, resolution: Resolution) {
+        let theirs = match self.conflicts.remove(&id) {
+            Some(theirs) => theirs,
+            None => return,
+        };
+        self.compare_open = false;
+        match resolution {
+            Resolution::KeepMine | Resolution::KeepBoth => {}
+            Resolution::TakeTheirs => notebook.insert(theirs.clone()),
+            Resolution::Merge => {
+                let base = notebook.saved(id).cloned();
+                notebook.update(id, |ours| {
+                    if let Some(merged) = base.as_ref().and_then(|base| ours.merge(base, &theirs)) {
+                        *ours = merged;
+                        return;
+                    }
+                    // both changed the same lines, every line of both is kept so nothing gets lost.
+                    // fields that were not touched here take the other value
+                    if base.as_ref().is_some_and(|base| base.title == ours.title) {
+                        ours.title = theirs.title.clone();
+                    }
+                    if base.as_ref().is_some_and(|base| base.color == ours.color) {
+                        ours.color = theirs.color;
+                    }
+                    ours.text = union_merge(&ours.text, &theirs.text);
+                });
+            }
+        }
+        // their version is what is in the store now, anything else is saved over it as usual
+        notebook.mark_saved_as(theirs);
+    }
+
+    // settles a note a sync could not merge: `original` has the other device's version and
+    // `copy` the one of this device, the version that is not kept goes to the trash
+    fn resolve_copy(&mut self, notebook: &mut Notebook, original: NoteId, copy: NoteId, resolution: Resolution, worker: &Worker, history: &mut History, revision_limit: usize) {
+        self.copies.remove(&original);
+        self.compare_open = false;
+        match resolution {
+            Resolution::KeepMine => {
+                let mine = match notebook.get(copy) {
+                    Some(mine) => mine.clone(),
+                    None => return,
+                };
+                notebook.update(original, |note| {
+                    note.title = mine.title.strip_suffix(Note::CONFLICTED_COPY).unwrap_or(&mine.title).to_owned();
+                    note.text = mine.text.clone();
+                    note.color = mine.color;
+                });
+                self.save_note(notebook, original, worker, revision_limit);
+            }
+            Resolution::TakeTheirs => {}
+            Resolution::Merge | Resolution::KeepBoth => return,
+        }
+        if notebook.delete(copy) {
+            history.record(copy, Edit::Delete);
+            worker.upsert(notebook.get(copy).unwrap().clone());
+        }
+    }
+
+    // records a revision and hands the note to the storage worker, failures show up as a toast
+    fn save_note(&mut self, notebook: &mut Notebook, id: NoteId, worker: &Worker, revision_limit: usize) {
+        if let Some(note) = notebook.get_mut(id) {
+            // saving without deciding keeps this version
+            self.conflicts.remove(&id);
+            note.record_revision(revision_limit);
+            worker.upsert(note.clone());
+            self.notifier.success("saved!");
+        }
+    }
+
+    // Save / Discard / Cancel when closing the editor with unsaved changes
+    fn closing_prompt(&mut self, ctx: &Context, notebook: &mut Notebook, id: NoteId, worker: &Worker, revision_limit: usize) {
+        let title = notebook.get(id).map(|note| note.title.clone()).unwrap_or_default();
+        let mut choice = None;
+        Window::new("Unsaved changes")
+            .id(Id::new("note_closing_window"))
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("'{}' has changes that were not saved yet.", title));
+                ui.add_space(5.);
+                choice = unsaved_buttons(ui);
+            });
+
+        match choice {
+            // if the write fails the note stays marked as unsaved, so nothing is lost
+            Some(Unsaved::Save) => {
+                self.save_note(notebook, id, worker, revision_limit);
+                self.editor_open = false;
+            }
+            Some(Unsaved::Discard) => {
+                notebook.discard(id);
+                self.editor_open = false;
+            }
+            Some(Unsaved::Cancel) => {}
+            None => return,
+        }
+        self.closing_window = false;
+    }
+
+    // line diff between two revisions of the note and rolling back to an older one,
+    // returns whether the note was rolled back
+    fn history_panel(&mut self, ui: &mut egui::Ui, note: &mut Note, revision_limit: usize) -> bool {
+        ui.label(RichText::new("History").strong());
+        if note.revisions.is_empty() {
+            ui.label(RichText::new("no revisions yet, every save adds one").small().weak());
+            return false;
+        }
+
+        let revision_name = |index: usize| format!("#{} {}", index + 1, local_time(&note.revisions[index].at));
+        let latest = note.revisions.len() - 1;
+        let mut from = self.history_from.filter(|index| *index <= latest).unwrap_or(latest);
+        let mut to = self.history_to.filter(|index| *index <= latest);
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("history_from")
+                .selected_text(revision_name(from))
+                .show_ui(ui, |ui| {
+                    for index in (0..=latest).rev() {
+                        ui.selectable_value(&mut from, index, revision_name(index));
+                    }
+                });
+            ui.label("→");
+            egui::ComboBox::from_id_source("history_to")
+                .selected_text(to.map_or("current".to_string(), revision_name))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut to, None, "current");
+                    for index in (0..=latest).rev() {
+                        ui.selectable_value(&mut to, Some(index), revision_name(index));
+                    }
+                });
+        });
+        self.history_from = Some(from);
+        self.history_to = to;
+
+        let old = &note.revisions[from];
+        let (new_title, new_text) = match to {
+            Some(index) => (&note.revisions[index].title, &note.revisions[index].text),
+            None => (&note.title, &note.text),
+        };
+        if old.title != *new_title {
+            ui.label(RichText::new(format!("title: {} → {}", old.title, new_title)).small());
+        }
+
+        egui::ScrollArea::vertical()
+            .max_height(300.)
+            .always_show_scroll(false)
+            .show(ui, |ui| {
+                for line in diff_lines(&old.text, new_text) {
+                    let text = match line {
+                        DiffLine::Same(line) => RichText::new(format!("  {}", line)).weak(),
+                        DiffLine::Added(line) => RichText::new(format!("+ {}", line)).color(Color32::from_rgb(90, 200, 120)),
+                        DiffLine::Removed(line) => RichText::new(format!("- {}", line)).color(Color32::from_rgb(230, 90, 90)),
+                    };
+                    ui.label(text.monospace());
+                }
+            });
+
+        ui.add_space(5.);
+        let rollback_btn = ui.button(format!("Roll back to {}", revision_name(from)))
+            .on_hover_text("Restores this revision, the current text stays in the history");
+        if rollback_btn.clicked() && note.rollback(from, revision_limit) {
+            self.history_from = None;
+            self.history_to = None;
+            return true;
+        }
+        false
+    }
+}
+
+// two versions of a note next to each other, the lines only one of them has are highlighted
+fn compare_panel(ui: &mut egui::Ui, (left_name, left): (&str, &Note), (right_name, right): (&str, &Note)) {
+    let diff = diff_lines(&left.text, &right.text);
+    ui.columns(2, |columns| {
+        compare_column(&mut columns[0], left_name, left, &diff, true);
+        compare_column(&mut columns[1], right_name, right, &diff, false);
+    });
+}
+
+fn compare_column(ui: &mut egui::Ui, name: &str, note: &Note, diff: &[DiffLine], left: bool) {
+    ui.label(RichText::new(name).strong());
+    ui.horizontal(|ui| {
+        ui.label(RichText::new("⏺").color(note_color(note)));
+        ui.label(note.title.as_str());
+    });
+    ui.label(RichText::new(format!("edited {}", local_time(&note.modified))).small().weak());
+    egui::ScrollArea::vertical()
+        .id_source(name)
+        .max_height(300.)
+        .always_show_scroll(false)
+        .show(ui, |ui| {
+            for line in diff {
+                let text = match (*line, left) {
+                    (DiffLine::Same(line), _) => RichText::new(line).weak(),
+                    (DiffLine::Removed(line), true) | (DiffLine::Added(line), false) => {
+                        RichText::new(line).color(Color32::from_rgb(230, 160, 60))
+                    }
+                    _ => continue,
+                };
+                ui.label(text.monospace());
+            }
+        });
 }
@@ -0,0 +1,118 @@
+use crate::{Note, NoteId};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Decides when a changed note should be written: once it has not been changed for
+/// `delay`, so typing does not cause a write for every key. While it is turned off no
+/// note is ever due, changes wait for the user to save them.
+pub struct Autosave {
+    delay: Duration,
+    enabled: bool,
+    // per changed note its last modification time and when that was first seen
+    pending: HashMap<NoteId, (DateTime<Utc>, Instant)>,
+}
+
+impl Autosave {
+    pub fn new(delay: Duration, enabled: bool) -> Self {
+        Autosave { delay, enabled, pending: HashMap::new() }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.pending.clear();
+        }
+    }
+
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+
+    /// Tells the autosave about a note with unsaved changes. Every new modification
+    /// time starts the delay over.
+    pub fn watch(&mut self, note: &Note) {
+        if !self.enabled {
+            return;
+        }
+        match self.pending.get(&note.id) {
+            Some((modified, _)) if *modified == note.modified => {}
+            _ => {
+                self.pending.insert(note.id, (note.modified, Instant::now()));
+            }
+        }
+    }
+
+    /// Stops waiting for the note, e.g. because it was saved some other way.
+    pub fn forget(&mut self, id: NoteId) {
+        self.pending.remove(&id);
+    }
+
+    /// The notes that were left alone for long enough, they are not pending anymore afterwards.
+    pub fn due(&mut self) -> Vec<NoteId> {
+        let delay = self.delay;
+        let due: Vec<NoteId> = self.pending.iter()
+            .filter(|(_, (_, since))| since.elapsed() >= delay)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &due {
+            self.pending.remove(id);
+        }
+        due
+    }
+
+    /// How long until the next note is due, if any are waiting.
+    pub fn next_due(&self) -> Option<Duration> {
+        self.pending.values()
+            .map(|(_, since)| self.delay.checked_sub(since.elapsed()).unwrap_or_default())
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note() -> Note {
+        Note::new(NoteId::new(), "text".to_string(), "title".to_string(), [0, 0, 0])
+    }
+
+    #[test]
+    fn notes_are_due_after_the_delay() {
+        let mut autosave = Autosave::new(Duration::from_secs(60), true);
+        let note = note();
+        autosave.watch(&note);
+        assert!(autosave.due().is_empty());
+        assert!(autosave.next_due().unwrap() > Duration::from_secs(59));
+
+        autosave.set_delay(Duration::ZERO);
+        assert_eq!(autosave.due(), vec![note.id]);
+        // written once, until it changes again
+        assert!(autosave.due().is_empty());
+        assert_eq!(autosave.next_due(), None);
+    }
+
+    #[test]
+    fn forgotten_notes_are_not_due() {
+        let mut autosave = Autosave::new(Duration::ZERO, true);
+        let note = note();
+        autosave.watch(&note);
+        autosave.forget(note.id);
+        assert!(autosave.due().is_empty());
+    }
+
+    #[test]
+    fn nothing_is_due_while_turned_off() {
+        let mut autosave = Autosave::new(Duration::ZERO, false);
+        let note = note();
+        autosave.watch(&note);
+        assert!(autosave.due().is_empty());
+        assert_eq!(autosave.next_due(), None);
+
+        // turning it off drops what was waiting
+        autosave.set_enabled(true);
+        autosave.watch(&note);
+        autosave.set_enabled(false);
+        assert!(autosave.due().is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+use super::{data_dir, is_read_only, StorageError};
+use chrono::{DateTime, Duration, Local, NaiveDateTime, TimeZone};
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How many backups are kept per file, older ones get deleted.
+pub const BACKUP_COUNT: usize = 5;
+
+// a new backup is only taken if the newest one is older than this,
+// otherwise every single save would push an older state out of the rotation
+const BACKUP_INTERVAL_MINUTES: i64 = 10;
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S%.3f";
+
+#[derive(Clone, Debug)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub created: DateTime<Local>,
+}
+
+// backups live in their own folder next to the data files
+pub fn backup_dir() -> Result<PathBuf, StorageError> {
+    let path = data_dir()?.join("backups");
+    fs::create_dir_all(&path).map_err(|e| StorageError::writing(&path, e))?;
+    Ok(path)
+}
+
+/// All backups of `file`, newest first.
+pub fn list_backups(file: &str) -> Result<Vec<Backup>, StorageError> {
+    let dir = backup_dir()?;
+    let prefix = format!("{}-", file);
+
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(&dir).map_err(|e| StorageError::reading(&dir, e))? {
+        let path = entry.map_err(|e| StorageError::reading(&dir, e))?.path();
+        let created = path.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|name| name.strip_suffix(".json"))
+            .and_then(|stamp| NaiveDateTime::parse_from_str(stamp, TIMESTAMP_FORMAT).ok())
+            .and_then(|stamp| Local.from_local_datetime(&stamp).earliest());
+        if let Some(created) = created {
+            backups.push(Backup { path, created });
+        }
+    }
+    backups.sort_by_key(|backup| Reverse(backup.created));
+    Ok(backups)
+}
+
+/// Copies the current state of `live` into the backup folder and drops the oldest
+/// backups beyond [`BACKUP_COUNT`]. Unless `force` is set this is skipped when a
+/// recent backup already exists.
+pub(crate) fn rotate(file: &str, live: &Path, force: bool) -> Result<(), StorageError> {
+    if is_read_only() {
+        return Err(StorageError::ReadOnly(live.to_path_buf()));
+    }
+    if !live.exists() {
+        return Ok(());
+    }
+
+    let mut backups = list_backups(file)?;
+    let recent = backups.first()
+        .map(|newest| Local::now() - newest.created < Duration::minutes(BACKUP_INTERVAL_MINUTES))
+        .unwrap_or(false);
+
+    if force || !recent {
+        let target = backup_dir()?.join(format!("{}-{}.json", file, Local::now().format(TIMESTAMP_FORMAT)));
+        fs::copy(live, &target).map_err(|e| StorageError::writing(&target, e))?;
+        backups = list_backups(file)?;
+    }
+
+    for old in backups.iter().skip(BACKUP_COUNT) {
+        match fs::remove_file(&old.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(StorageError::writing(&old.path, e)),
+            _ => debug!("removed old backup '{}'", old.path.display()),
+        }
+    }
+    Ok(())
+}
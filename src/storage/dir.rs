@@ -0,0 +1,116 @@
+use super::{is_read_only, read_json, upgrade_note, write_json, NoteId, NoteMeta, NoteStore, StorageError, SCHEMA_VERSION};
+use crate::model::repair_ids;
+use crate::Note;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Stores every note as its own `<id>.json` file inside a directory,
+/// so a change only rewrites the note that was touched.
+/// Each file is `{ "version": N, "note": {...} }`, bare note objects are read as version 0.
+#[derive(Clone, Debug)]
+pub struct DirStore {
+    dir: PathBuf,
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u64,
+    note: &'a Note,
+}
+
+impl DirStore {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, StorageError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| StorageError::writing(&dir, e))?;
+        Ok(DirStore { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn note_path(&self, id: NoteId) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn note_paths(&self) -> Result<Vec<PathBuf>, StorageError> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&self.dir).map_err(|e| StorageError::reading(&self.dir, e))? {
+            let path = entry.map_err(|e| StorageError::reading(&self.dir, e))?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+fn read_note(path: &Path) -> Result<Note, StorageError> {
+    match read_json(path)? {
+        Value::Object(mut envelope) if envelope.contains_key("note") => {
+            let version = envelope.get("version").and_then(Value::as_u64).unwrap_or(0);
+            upgrade_note(envelope.remove("note").unwrap_or(Value::Null), version, path)
+        }
+        note => upgrade_note(note, 0, path),
+    }
+}
+
+impl NoteStore for DirStore {
+    fn load_all(&mut self) -> Result<Vec<Note>, StorageError> {
+        let paths = self.note_paths()?;
+        let mut notes = paths.iter().map(|path| read_note(path)).collect::<Result<Vec<Note>, StorageError>>()?;
+        repair_ids(&mut notes);
+
+        if is_read_only() {
+            return Ok(notes);
+        }
+
+        // files named after a legacy or repaired id are moved to the right name
+        for (path, note) in paths.iter().zip(&notes) {
+            let expected = self.note_path(note.id);
+            if *path != expected {
+                self.upsert(note)?;
+                if !notes.iter().any(|other| self.note_path(other.id) == *path) {
+                    fs::remove_file(path).map_err(|e| StorageError::writing(path, e))?;
+                }
+            }
+        }
+        Ok(notes)
+    }
+
+    fn get(&self, id: NoteId) -> Result<Option<Note>, StorageError> {
+        match read_note(&self.note_path(id)) {
+            Ok(note) => Ok(Some(note)),
+            Err(StorageError::Missing(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn upsert(&mut self, note: &Note) -> Result<(), StorageError> {
+        write_json(&self.note_path(note.id), &Envelope { version: SCHEMA_VERSION, note })
+    }
+
+    fn delete(&mut self, id: NoteId) -> Result<bool, StorageError> {
+        let path = self.note_path(id);
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(StorageError::writing(&path, e)),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<NoteMeta>, StorageError> {
+        self.note_paths()?
+            .iter()
+            .map(|path| read_note(path).map(|note| NoteMeta::from(&note)))
+            .collect()
+    }
+
+    fn location(&self) -> Option<PathBuf> {
+        Some(self.dir.clone())
+    }
+}
@@ -0,0 +1,121 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// raw os error code for "no space left on device" (ENOSPC on unix, ERROR_DISK_FULL on windows)
+#[cfg(unix)]
+const DISK_FULL: i32 = 28;
+#[cfg(windows)]
+const DISK_FULL: i32 = 112;
+#[cfg(not(any(unix, windows)))]
+const DISK_FULL: i32 = -1;
+
+/// Everything that can go wrong while reading or writing the note store.
+#[derive(Debug)]
+pub enum StorageError {
+    /// the file does not exist (yet)
+    Missing(PathBuf),
+    /// the file exists but could not be read
+    Unreadable { path: PathBuf, source: io::Error },
+    /// the file could not be written
+    Unwritable { path: PathBuf, source: io::Error },
+    /// the file was read but does not contain valid data
+    Corrupt { path: PathBuf, source: Box<dyn std::error::Error + Send + Sync> },
+    /// the file was written by a newer version of the app
+    UnsupportedVersion { path: PathBuf, version: u64 },
+    /// the sqlite database reported an error
+    Database { path: PathBuf, source: rusqlite::Error },
+    PermissionDenied(PathBuf),
+    DiskFull(PathBuf),
+    /// another copy of the app owns the data dir, see [`Instance`](super::Instance)
+    ReadOnly(PathBuf),
+}
+
+impl StorageError {
+    /// Classifies an io error that happened while reading `path`.
+    pub fn reading(path: &Path, err: io::Error) -> Self {
+        match Self::classify(path, &err) {
+            Some(e) => e,
+            None => StorageError::Unreadable { path: path.to_path_buf(), source: err },
+        }
+    }
+
+    /// Classifies an io error that happened while writing `path`.
+    pub fn writing(path: &Path, err: io::Error) -> Self {
+        match Self::classify(path, &err) {
+            Some(e) => e,
+            None => StorageError::Unwritable { path: path.to_path_buf(), source: err },
+        }
+    }
+
+    /// Classifies an error reported by the sqlite database at `path`.
+    pub fn database(path: &Path, err: rusqlite::Error) -> Self {
+        use rusqlite::ErrorCode;
+
+        let path = path.to_path_buf();
+        let code = match &err {
+            rusqlite::Error::SqliteFailure(failure, _) => Some(failure.code),
+            _ => None,
+        };
+        match code {
+            Some(ErrorCode::DiskFull) => StorageError::DiskFull(path),
+            Some(ErrorCode::PermissionDenied) | Some(ErrorCode::ReadOnly) => StorageError::PermissionDenied(path),
+            _ => StorageError::Database { path, source: err },
+        }
+    }
+
+    fn classify(path: &Path, err: &io::Error) -> Option<Self> {
+        let path = path.to_path_buf();
+        match err.kind() {
+            io::ErrorKind::NotFound => Some(StorageError::Missing(path)),
+            io::ErrorKind::PermissionDenied => Some(StorageError::PermissionDenied(path)),
+            _ if err.raw_os_error() == Some(DISK_FULL) => Some(StorageError::DiskFull(path)),
+            _ => None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            StorageError::Missing(path)
+            | StorageError::PermissionDenied(path)
+            | StorageError::DiskFull(path)
+            | StorageError::ReadOnly(path) => path,
+            StorageError::Unreadable { path, .. }
+            | StorageError::Unwritable { path, .. }
+            | StorageError::Corrupt { path, .. }
+            | StorageError::UnsupportedVersion { path, .. }
+            | StorageError::Database { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Missing(path) => write!(f, "'{}' does not exist", path.display()),
+            StorageError::Unreadable { path, source } => write!(f, "could not read '{}': {}", path.display(), source),
+            StorageError::Unwritable { path, source } => write!(f, "could not write '{}': {}", path.display(), source),
+            StorageError::Corrupt { path, source } => write!(f, "'{}' is corrupt: {}", path.display(), source),
+            StorageError::UnsupportedVersion { path, version } => {
+                write!(f, "'{}' uses format version {}, which is newer than this app supports", path.display(), version)
+            }
+            StorageError::Database { path, source } => write!(f, "database error in '{}': {}", path.display(), source),
+            StorageError::PermissionDenied(path) => write!(f, "permission denied for '{}'", path.display()),
+            StorageError::DiskFull(path) => write!(f, "no space left to write '{}'", path.display()),
+            StorageError::ReadOnly(path) => {
+                write!(f, "'{}' is read-only, another copy of the app is using it", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StorageError::Unreadable { source, .. } | StorageError::Unwritable { source, .. } => Some(source),
+            StorageError::Corrupt { source, .. } => Some(source.as_ref()),
+            StorageError::Database { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use super::{data_dir, NoteId, NoteMeta, NoteStore, StorageError};
+use crate::toast::Notifier;
+use crate::Note;
+use fs2::FileExt;
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+#[cfg(unix)]
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    thread,
+    time::Duration,
+};
+
+const LOCK_FILE: &str = ".lock";
+#[cfg(unix)]
+const SOCKET_FILE: &str = "snow.sock";
+// what a second copy of the app sends to the first one
+#[cfg(unix)]
+const SHOW: &str = "show";
+
+// set once another copy of the app owns the data dir, see `is_read_only`
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Whether this copy of the app must not write any notes, because another copy holds the
+/// lock on the data dir. [`open`](super::open) hands out read-only stores in that case.
+pub fn is_read_only() -> bool {
+    READ_ONLY.load(Ordering::SeqCst)
+}
+
+/// What became of this copy of the app when it tried to lock the data dir.
+pub enum Instance {
+    /// the only running copy, it may write as long as the lock is kept alive
+    Primary(InstanceLock),
+    /// another copy is running and was asked to show itself, this one should just exit
+    Forwarded,
+    /// another copy is running but could not be reached, notes are only read
+    ReadOnly,
+}
+
+impl Instance {
+    /// Takes the advisory lock on the data dir. If another copy of the app has it, that copy
+    /// is asked to show itself instead, if that fails as well everything is opened read-only.
+    pub fn acquire() -> Result<Instance, StorageError> {
+        let dir = data_dir()?;
+        let path = dir.join(LOCK_FILE);
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(&path)
+            .map_err(|e| StorageError::writing(&path, e))?;
+
+        if file.try_lock_exclusive().is_ok() {
+            #[cfg(unix)]
+            let listener = {
+                // whoever created it is gone, otherwise we would not have the lock
+                let socket = dir.join(SOCKET_FILE);
+                let _ = std::fs::remove_file(&socket);
+                UnixListener::bind(&socket)
+                    .map_err(|e| warn!("[Snow]: a second launch will not find this window: {}", e))
+                    .ok()
+                    .map(|listener| (listener, socket))
+            };
+            return Ok(Instance::Primary(InstanceLock {
+                _file: file,
+                #[cfg(unix)]
+                listener,
+            }));
+        }
+
+        #[cfg(unix)]
+        if forward(&dir.join(SOCKET_FILE)) {
+            info!("[Snow]: already running, asked the other window to show itself");
+            return Ok(Instance::Forwarded);
+        }
+
+        warn!("[Snow]: '{}' is locked by another copy of the app, opening the notes read-only", dir.display());
+        READ_ONLY.store(true, Ordering::SeqCst);
+        Ok(Instance::ReadOnly)
+    }
+}
+
+// tells the copy of the app listening on `socket` to show itself
+#[cfg(unix)]
+fn forward(socket: &std::path::Path) -> bool {
+    // the other copy might have taken the lock a moment ago and not be listening yet
+    for _ in 0..10 {
+        if let Ok(mut stream) = UnixStream::connect(socket) {
+            return writeln!(stream, "{}", SHOW).is_ok();
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    false
+}
+
+/// The lock on the data dir, released once this is dropped or the app exits.
+pub struct InstanceLock {
+    _file: File,
+    #[cfg(unix)]
+    listener: Option<(UnixListener, PathBuf)>,
+}
+
+impl InstanceLock {
+    /// Listens for later launches of the app. The returned flag is set and the ui woken up
+    /// with `notifier` whenever one asked this copy to show itself.
+    pub fn listen(&mut self, notifier: Notifier) -> Arc<AtomicBool> {
+        let shown = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        if let Some(listener) = self.listener.as_ref().and_then(|(listener, _)| listener.try_clone().ok()) {
+            let flag = shown.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let mut line = String::new();
+                    if BufReader::new(stream).read_line(&mut line).is_ok() && line.trim() == SHOW {
+                        flag.store(true, Ordering::SeqCst);
+                        notifier.wake();
+                    }
+                }
+            });
+        }
+        #[cfg(not(unix))]
+        let _ = notifier;
+        shown
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some((_, socket)) = &self.listener {
+            let _ = std::fs::remove_file(socket);
+        }
+    }
+}
+
+/// Wraps the store of a copy of the app that does not own the data dir: reads are passed
+/// through, every write fails with [`StorageError::ReadOnly`].
+pub struct ReadOnlyStore {
+    inner: Box<dyn NoteStore>,
+}
+
+impl ReadOnlyStore {
+    pub fn new(inner: Box<dyn NoteStore>) -> Self {
+        ReadOnlyStore { inner }
+    }
+
+    fn refuse(&self) -> StorageError {
+        StorageError::ReadOnly(self.inner.location().unwrap_or_default())
+    }
+}
+
+impl NoteStore for ReadOnlyStore {
+    fn load_all(&mut self) -> Result<Vec<Note>, StorageError> {
+        self.inner.load_all()
+    }
+
+    fn get(&self, id: NoteId) -> Result<Option<Note>, StorageError> {
+        self.inner.get(id)
+    }
+
+    fn upsert(&mut self, _note: &Note) -> Result<(), StorageError> {
+        Err(self.refuse())
+    }
+
+    fn delete(&mut self, _id: NoteId) -> Result<bool, StorageError> {
+        Err(self.refuse())
+    }
+
+    fn list(&self) -> Result<Vec<NoteMeta>, StorageError> {
+        self.inner.list()
+    }
+
+    fn location(&self) -> Option<PathBuf> {
+        self.inner.location()
+    }
+
+    fn changed_externally(&self) -> bool {
+        self.inner.changed_externally()
+    }
+
+    fn save_all(&mut self, _notes: &[Note]) -> Result<(), StorageError> {
+        Err(self.refuse())
+    }
+}
@@ -0,0 +1,149 @@
+use super::{data_path, fnv1a, load_or_init_notes, save_notes, NoteId, NoteMeta, NoteStore, StorageError};
+use crate::Note;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The original storage format: every note in a single json file inside the app data dir,
+/// see [`SCHEMA_VERSION`](super::SCHEMA_VERSION) for its layout. The whole file is rewritten on every change.
+///
+/// If the file was changed by someone else since it was last read, it is read again before
+/// writing, so only the notes that actually changed here are overwritten.
+#[derive(Clone, Debug)]
+pub struct JsonFileStore {
+    file: String,
+    notes: Vec<Note>,
+    // the file after we last read or wrote it, see `fingerprint`
+    seen: Option<Fingerprint>,
+}
+
+impl JsonFileStore {
+    /// `file` is the name of the data file without extension, e.g. `"data"`.
+    pub fn new(file: &str) -> Self {
+        JsonFileStore { file: file.to_string(), notes: Vec::new(), seen: None }
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    fn read(&mut self) -> Result<(), StorageError> {
+        self.notes = load_or_init_notes(&self.file)?;
+        self.seen = fingerprint(&self.file);
+        Ok(())
+    }
+
+    // applies `change` on top of the latest contents of the file and writes it back
+    fn change<T>(&mut self, change: impl FnOnce(&mut Vec<Note>) -> T) -> Result<T, StorageError> {
+        if self.changed_externally() {
+            warn!("[Snow]: '{}' was changed by another program, reading it again before saving", self.file);
+            self.read()?;
+        }
+        let result = change(&mut self.notes);
+        save_notes(&self.file, &self.notes)?;
+        self.seen = fingerprint(&self.file);
+        Ok(result)
+    }
+}
+
+// modification time, size and a hash of the contents. The time alone misses a write
+// within the same tick of a coarse clock, the size one that keeps the length
+type Fingerprint = (SystemTime, u64, u64);
+
+fn fingerprint(file: &str) -> Option<Fingerprint> {
+    fingerprint_of(&data_path(file).ok()?)
+}
+
+fn fingerprint_of(path: &Path) -> Option<Fingerprint> {
+    let meta = fs::metadata(path).ok()?;
+    let contents = fs::read(path).ok()?;
+    Some((meta.modified().ok()?, meta.len(), fnv1a(&contents)))
+}
+
+impl NoteStore for JsonFileStore {
+    fn load_all(&mut self) -> Result<Vec<Note>, StorageError> {
+        self.read()?;
+        Ok(self.notes.clone())
+    }
+
+    fn get(&self, id: NoteId) -> Result<Option<Note>, StorageError> {
+        Ok(self.notes.iter().find(|note| note.id == id).cloned())
+    }
+
+    fn upsert(&mut self, note: &Note) -> Result<(), StorageError> {
+        self.change(|notes| put(notes, note))
+    }
+
+    fn delete(&mut self, id: NoteId) -> Result<bool, StorageError> {
+        if !self.notes.iter().any(|note| note.id == id) && !self.changed_externally() {
+            return Ok(false);
+        }
+        self.change(|notes| {
+            let len = notes.len();
+            notes.retain(|note| note.id != id);
+            notes.len() != len
+        })
+    }
+
+    fn list(&self) -> Result<Vec<NoteMeta>, StorageError> {
+        Ok(self.notes.iter().map(NoteMeta::from).collect())
+    }
+
+    fn location(&self) -> Option<PathBuf> {
+        data_path(&self.file).ok()
+    }
+
+    /// Whether the file changed since this store last read or wrote it.
+    fn changed_externally(&self) -> bool {
+        self.seen.is_some() && fingerprint(&self.file) != self.seen
+    }
+
+    // one write instead of one per note. If someone else changed the file in the meantime,
+    // only the notes that changed here since it was last read go over their changes
+    fn save_all(&mut self, notes: &[Note]) -> Result<(), StorageError> {
+        if !self.changed_externally() {
+            self.notes = notes.to_vec();
+            save_notes(&self.file, &self.notes)?;
+            self.seen = fingerprint(&self.file);
+            return Ok(());
+        }
+        let base = self.notes.clone();
+        self.change(|stored| {
+            for note in notes.iter().filter(|note| !base.contains(note)) {
+                put(stored, note);
+            }
+            // removed here, notes added by the other program are kept
+            stored.retain(|note| notes.iter().any(|ours| ours.id == note.id) || !base.iter().any(|old| old.id == note.id));
+        })
+    }
+}
+
+fn put(notes: &mut Vec<Note>, note: &Note) {
+    match notes.iter_mut().find(|stored| stored.id == note.id) {
+        Some(stored) => *stored = note.clone(),
+        None => notes.push(note.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_keeping_time_and_size_are_noticed() {
+        let path = std::env::temp_dir().join(format!("snow-treading-fingerprint-{}.json", NoteId::new()));
+        fs::write(&path, r#"{"text": "milk"}"#).unwrap();
+        let before = fingerprint_of(&path).unwrap();
+        assert_eq!(fingerprint_of(&path), Some(before));
+
+        // another program writes within the same tick
+        fs::write(&path, r#"{"text": "eggs"}"#).unwrap();
+        fs::File::options().write(true).open(&path).unwrap().set_modified(before.0).unwrap();
+        let after = fingerprint_of(&path).unwrap();
+        assert_eq!((after.0, after.1), (before.0, before.1));
+        assert_ne!(after, before);
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(fingerprint_of(&path), None);
+    }
+}
@@ -0,0 +1,196 @@
+use super::StorageError;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Environment variable that overrides where the notes are kept.
+pub const DATA_DIR_VAR: &str = "SNOW_TREADING_DATA";
+
+// name of the app's directories below the platform data and config dirs
+const APP_DIR: &str = "snow-treading";
+// where everything lived before the data dir could be configured
+const LEGACY_DIR: &str = ".snow-treading";
+// a file with this name next to the executable turns on portable mode
+const PORTABLE_MARKER: &str = "portable";
+const CONFIG_FILE: &str = "config.toml";
+// the lock and socket of a running instance belong to that instance, they never move
+const NOT_MOVED: &[&str] = &[".lock", "snow.sock"];
+
+// chosen once at startup with `set_data_dir`, changes when the data is moved
+static DATA_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Where the data dir in use was taken from, with the most important source first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// the `--data-dir` command line flag
+    Flag,
+    /// the [`DATA_DIR_VAR`] environment variable
+    Env,
+    /// the `data_dir` of the app config
+    Config,
+    /// next to the executable
+    Portable,
+    /// the platform default, e.g. `$XDG_DATA_HOME/snow-treading` on linux
+    Default,
+}
+
+impl Source {
+    /// Whether the data dir can be changed from within the app. Flags and the environment
+    /// win over the config, so moving the data would not stick.
+    pub fn is_configurable(&self) -> bool {
+        !matches!(self, Source::Flag | Source::Env)
+    }
+}
+
+/// Where the app keeps its config file and notes.
+#[derive(Clone, Debug)]
+pub struct Location {
+    pub data_dir: PathBuf,
+    pub source: Source,
+    pub config_file: PathBuf,
+    pub portable: bool,
+}
+
+impl Location {
+    /// The config file to read the configured data dir from, before the data dir is known.
+    /// `portable` forces portable mode, otherwise it is on if a file named `portable`
+    /// sits next to the executable.
+    pub fn config_file(portable: bool) -> Result<(PathBuf, bool), StorageError> {
+        let portable = portable || exe_dir().is_ok_and(|dir| dir.join(PORTABLE_MARKER).exists());
+        let dir = if portable {
+            exe_dir()?
+        } else {
+            dirs_2::config_dir().ok_or_else(|| missing("the config directory"))?.join(APP_DIR)
+        };
+        Ok((dir.join(CONFIG_FILE), portable))
+    }
+
+    /// Picks the data dir: the flag beats the environment, which beats the config,
+    /// which beats the default of the mode the app runs in.
+    pub fn resolve(flag: Option<PathBuf>, configured: Option<PathBuf>, portable: bool) -> Result<Self, StorageError> {
+        let (config_file, portable) = Location::config_file(portable)?;
+        let env = std::env::var_os(DATA_DIR_VAR).filter(|dir| !dir.is_empty()).map(PathBuf::from);
+
+        let (data_dir, source) = match (flag, env, configured) {
+            (Some(dir), _, _) => (dir, Source::Flag),
+            (None, Some(dir), _) => (dir, Source::Env),
+            (None, None, Some(dir)) => (dir, Source::Config),
+            (None, None, None) if portable => (exe_dir()?.join(APP_DIR), Source::Portable),
+            (None, None, None) => (default_data_dir()?, Source::Default),
+        };
+        Ok(Location { data_dir, source, config_file, portable })
+    }
+}
+
+/// Makes `dir` the data dir for everything that is read or written from now on.
+pub fn set_data_dir(dir: impl Into<PathBuf>) {
+    *DATA_DIR.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(dir.into());
+}
+
+/// The data dir set with [`set_data_dir`], or the platform default if there is none.
+pub(crate) fn current_data_dir() -> Result<PathBuf, StorageError> {
+    let dir = DATA_DIR.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    match dir {
+        Some(dir) => Ok(dir),
+        None => default_data_dir(),
+    }
+}
+
+// the platform data dir, unless the notes still live where older builds put them
+fn default_data_dir() -> Result<PathBuf, StorageError> {
+    let dir = dirs_2::data_dir().ok_or_else(|| missing("the data directory"))?.join(APP_DIR);
+    if !dir.exists() {
+        if let Some(legacy) = dirs_2::home_dir().map(|home| home.join(LEGACY_DIR)).filter(|legacy| legacy.is_dir()) {
+            return Ok(legacy);
+        }
+    }
+    Ok(dir)
+}
+
+fn exe_dir() -> Result<PathBuf, StorageError> {
+    let exe = std::env::current_exe().map_err(|e| StorageError::reading(Path::new("."), e))?;
+    Ok(exe.parent().map(Path::to_path_buf).unwrap_or_default())
+}
+
+fn missing(what: &str) -> StorageError {
+    StorageError::Unreadable {
+        path: PathBuf::from("~"),
+        source: io::Error::new(io::ErrorKind::NotFound, format!("could not find {}", what)),
+    }
+}
+
+/// Copies everything in the data dir `from` to `to`, which must not contain anything yet.
+/// Every copy is checked against its original. Returns the copied files relative to `from`,
+/// so they can be removed with [`remove_data`] once the app uses `to`.
+pub fn copy_data(from: &Path, to: &Path) -> Result<Vec<PathBuf>, StorageError> {
+    if to.starts_with(from) || from.starts_with(to) {
+        let e = io::Error::new(io::ErrorKind::InvalidInput, "the new data directory must not contain the old one or lie inside it");
+        return Err(StorageError::writing(to, e));
+    }
+    let occupied = fs::read_dir(to).is_ok_and(|mut entries| entries.next().is_some());
+    if occupied {
+        let e = io::Error::new(io::ErrorKind::AlreadyExists, "the new data directory is not empty");
+        return Err(StorageError::writing(to, e));
+    }
+
+    let mut files = Vec::new();
+    list_files(from, Path::new(""), &mut files)?;
+    let result = files.iter().try_for_each(|file| {
+        let (source, target) = (from.join(file), to.join(file));
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| StorageError::writing(parent, e))?;
+        }
+        fs::copy(&source, &target).map_err(|e| StorageError::writing(&target, e))?;
+        let same = fs::read(&source).map_err(|e| StorageError::reading(&source, e))?
+            == fs::read(&target).map_err(|e| StorageError::reading(&target, e))?;
+        if !same {
+            let e = io::Error::new(io::ErrorKind::InvalidData, "the copy differs from the original");
+            return Err(StorageError::writing(&target, e));
+        }
+        Ok(())
+    });
+
+    // a half done copy is of no use to anyone, the originals are still in place
+    if let Err(e) = result {
+        let _ = remove_data(to, &files);
+        return Err(e);
+    }
+    info!("[Snow]: copied {} files from '{}' to '{}'", files.len(), from.display(), to.display());
+    Ok(files)
+}
+
+/// Removes the `files` returned by [`copy_data`] from `dir`, and directories that are left empty.
+/// Anything else in `dir` is left alone.
+pub fn remove_data(dir: &Path, files: &[PathBuf]) -> Result<(), StorageError> {
+    for file in files {
+        let path = dir.join(file);
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(StorageError::writing(&path, e)),
+            _ => {}
+        }
+    }
+    // deepest first, `remove_dir` refuses directories that still have something in them
+    let mut dirs: Vec<PathBuf> = files.iter().filter_map(|file| file.parent()).flat_map(Path::ancestors).map(Path::to_path_buf).collect();
+    dirs.sort_by(|a, b| b.components().count().cmp(&a.components().count()).then(a.cmp(b)));
+    dirs.dedup();
+    for sub in dirs.iter().filter(|sub| !sub.as_os_str().is_empty()) {
+        let _ = fs::remove_dir(dir.join(sub));
+    }
+    Ok(())
+}
+
+fn list_files(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<(), StorageError> {
+    let dir = root.join(relative);
+    for entry in fs::read_dir(&dir).map_err(|e| StorageError::reading(&dir, e))? {
+        let entry = entry.map_err(|e| StorageError::reading(&dir, e))?;
+        let path = relative.join(entry.file_name());
+        let kind = entry.file_type().map_err(|e| StorageError::reading(&entry.path(), e))?;
+        if kind.is_dir() {
+            list_files(root, &path, files)?;
+        } else if kind.is_file() && !(relative.as_os_str().is_empty() && NOT_MOVED.iter().any(|name| entry.file_name() == *name)) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use super::{NoteId, NoteMeta, NoteStore, StorageError};
+use crate::Note;
+
+/// Keeps notes in memory only, nothing survives a restart. Mostly useful for tests.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    notes: Vec<Note>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_notes(notes: Vec<Note>) -> Self {
+        MemoryStore { notes }
+    }
+}
+
+impl NoteStore for MemoryStore {
+    fn load_all(&mut self) -> Result<Vec<Note>, StorageError> {
+        Ok(self.notes.clone())
+    }
+
+    fn get(&self, id: NoteId) -> Result<Option<Note>, StorageError> {
+        Ok(self.notes.iter().find(|note| note.id == id).cloned())
+    }
+
+    fn upsert(&mut self, note: &Note) -> Result<(), StorageError> {
+        match self.notes.iter_mut().find(|stored| stored.id == note.id) {
+            Some(stored) => *stored = note.clone(),
+            None => self.notes.push(note.clone()),
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, id: NoteId) -> Result<bool, StorageError> {
+        let len = self.notes.len();
+        self.notes.retain(|note| note.id != id);
+        Ok(self.notes.len() != len)
+    }
+
+    fn list(&self) -> Result<Vec<NoteMeta>, StorageError> {
+        Ok(self.notes.iter().map(NoteMeta::from).collect())
+    }
+
+    fn save_all(&mut self, notes: &[Note]) -> Result<(), StorageError> {
+        self.notes = notes.to_vec();
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+mod autosave;
+mod backup;
+mod dir;
+mod error;
+mod instance;
+mod json;
+mod location;
+mod memory;
+mod schema;
+mod sqlite;
+mod store;
+mod vault;
+mod watch;
+mod worker;
+
+pub use autosave::Autosave;
+pub use backup::{backup_dir, list_backups, Backup, BACKUP_COUNT};
+pub use dir::DirStore;
+pub use error::StorageError;
+pub use instance::{is_read_only, Instance, InstanceLock, ReadOnlyStore};
+pub use json::JsonFileStore;
+pub use location::{copy_data, remove_data, set_data_dir, Location, Source, DATA_DIR_VAR};
+pub use memory::MemoryStore;
+pub use schema::{load_notes, load_or_init_notes, parse_legacy_date, save_notes, upgrade_collection, upgrade_note, SCHEMA_VERSION};
+pub use sqlite::SqliteStore;
+pub use store::{NoteMeta, NoteStore};
+pub use crate::model::NoteId;
+pub use vault::{slugify, VaultStore};
+pub use watch::StoreWatcher;
+pub use worker::{Job, Report, Worker};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A store that can be written to from other threads, see [`Worker`].
+pub type SharedStore = Arc<Mutex<Box<dyn NoteStore>>>;
+
+pub fn shared(store: Box<dyn NoteStore>) -> SharedStore {
+    Arc::new(Mutex::new(store))
+}
+
+/// Locks the store. A write that panicked can not have left the store itself in a broken
+/// state (files are written atomically), so a poisoned lock is simply taken over.
+pub fn lock(store: &SharedStore) -> MutexGuard<'_, Box<dyn NoteStore>> {
+    store.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The app data dir (see [`set_data_dir`]), created if it does not exist yet.
+pub fn data_dir() -> Result<PathBuf, StorageError> {
+    let path = location::current_data_dir()?;
+    fs::create_dir_all(&path).map_err(|e| StorageError::writing(&path, e))?;
+    Ok(path)
+}
+
+// path of the given file inside the app data dir
+pub fn data_path(file: &str) -> Result<PathBuf, StorageError> {
+    Ok(data_dir()?.join(format!("{}.json", file)))
+}
+
+/// Which backend the notes are persisted with, chosen in the app config.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// every note in `data.json`
+    #[default]
+    Json,
+    /// one row per note in `notes.sqlite3`
+    Sqlite,
+    /// one markdown file per note in the given directory
+    Vault(PathBuf),
+}
+
+/// Opens the store for `backend` inside the app data dir. Switching to sqlite
+/// imports an existing `data.json` the first time. While another copy of the app
+/// owns the data dir the store is wrapped in a [`ReadOnlyStore`].
+pub fn open(backend: &StorageBackend) -> Result<Box<dyn NoteStore>, StorageError> {
+    let store: Box<dyn NoteStore> = match backend {
+        StorageBackend::Json => Box::new(JsonFileStore::new("data")),
+        StorageBackend::Sqlite => {
+            let mut store = SqliteStore::open(data_dir()?.join("notes.sqlite3"))?;
+            store.import_json("data")?;
+            Box::new(store)
+        }
+        StorageBackend::Vault(dir) => Box::new(VaultStore::open(dir)?),
+    };
+    if is_read_only() {
+        return Ok(Box::new(ReadOnlyStore::new(store)));
+    }
+    Ok(store)
+}
+
+// where the vault lives unless the user picks another directory
+pub fn default_vault_dir() -> Result<PathBuf, StorageError> {
+    Ok(data_dir()?.join("vault"))
+}
+
+pub fn load_file<T: DeserializeOwned>(file: &str) -> Result<Vec<T>, StorageError> {
+    read_json(&data_path(file)?)
+}
+
+/// Like [`load_file`], but a missing file is treated as a first run and an empty store is created.
+pub fn load_or_init<T: Serialize + DeserializeOwned>(file: &str) -> Result<Vec<T>, StorageError> {
+    match load_file(file) {
+        Err(StorageError::Missing(path)) => {
+            info!("[Snow]: no data found at '{}', creating an empty store", path.display());
+            save_file(file, Vec::<T>::new())?;
+            Ok(Vec::new())
+        }
+        result => result,
+    }
+}
+
+pub fn save_file<T: Serialize>(file_name: &str, data: T) -> Result<(), StorageError> {
+    let path = data_path(file_name)?;
+
+    // keep older states around in case the user wants to go back
+    backup::rotate(file_name, &path, false)?;
+
+    write_json(&path, &data)?;
+    info!("saved '{}' to '{}'!", file_name, path.display());
+    Ok(())
+}
+
+/// A hash that stays the same across builds and rust versions, unlike the one of `std::hash`.
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+pub fn has_backup(file: &str) -> bool {
+    list_backups(file).map(|backups| !backups.is_empty()).unwrap_or(false)
+}
+
+/// Replaces the live file with the given backup, as long as the backup itself is readable.
+/// The current state is backed up first, so a restore can be undone the same way.
+pub fn restore_backup(file: &str, backup: &Backup) -> Result<(), StorageError> {
+    let data: serde_json::Value = read_json(&backup.path)?;
+
+    let path = data_path(file)?;
+    // a corrupt live file is not worth keeping
+    if read_json::<serde_json::Value>(&path).is_ok() {
+        backup::rotate(file, &path, true)?;
+    }
+    write_json(&path, &data)?;
+    warn!("[Snow]: restored '{}' from '{}'", path.display(), backup.path.display());
+    Ok(())
+}
+
+/// Moves an unreadable live file out of the way so a fresh store can be started
+/// without throwing the old data away. Returns where the file was moved to.
+pub fn quarantine(file: &str) -> Result<PathBuf, StorageError> {
+    let path = data_path(file)?;
+    let mut target = path.clone();
+    target.set_extension(format!("json.corrupt-{}", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+    fs::rename(&path, &target).map_err(|e| StorageError::writing(&path, e))?;
+    warn!("[Snow]: moved '{}' to '{}'", path.display(), target.display());
+    Ok(target)
+}
+
+pub(crate) fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, StorageError> {
+    let file = File::open(path).map_err(|e| StorageError::reading(path, e))?;
+    let reader = BufReader::new(file);
+    serde_json::from_reader(reader).map_err(|e| {
+        if e.is_io() {
+            StorageError::reading(path, e.into())
+        } else {
+            StorageError::Corrupt { path: path.to_path_buf(), source: e.into() }
+        }
+    })
+}
+
+pub(crate) fn write_json<T: Serialize>(path: &Path, data: &T) -> Result<(), StorageError> {
+    let contents = serde_json::to_vec_pretty(data).map_err(|e| StorageError::writing(path, e.into()))?;
+    write_atomic(path, &contents)
+}
+
+// writes into a temporary file next to `path` and renames it over the original once
+// everything is on disk, so a crash mid-write never leaves a half written file behind
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), StorageError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    write_atomic_with(path, contents, &options)
+}
+
+/// Like [`write_atomic`], but only the current user may read the file, e.g. for login tokens.
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> Result<(), StorageError> {
+    let mut options = OpenOptions::new();
+    // created fresh, the permissions only apply to new files
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    write_atomic_with(path, contents, &options)
+}
+
+fn write_atomic_with(path: &Path, contents: &[u8], options: &OpenOptions) -> Result<(), StorageError> {
+    if is_read_only() {
+        return Err(StorageError::ReadOnly(path.to_path_buf()));
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    // left over from a crash
+    let _ = fs::remove_file(&tmp);
+    let result = (|| {
+        let mut file = options.open(&tmp).map_err(|e| StorageError::writing(&tmp, e))?;
+        file.write_all(contents).map_err(|e| StorageError::writing(&tmp, e))?;
+        file.sync_all().map_err(|e| StorageError::writing(&tmp, e))?;
+        fs::rename(&tmp, path).map_err(|e| StorageError::writing(path, e))
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return result;
+    }
+
+    // make the rename itself durable
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        File::open(dir).and_then(|dir| dir.sync_all()).map_err(|e| StorageError::writing(dir, e))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,273 @@
+use super::{backup, data_path, is_read_only, read_json, save_file, StorageError};
+use crate::model::repair_ids;
+use crate::{Note, NoteId};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::path::Path;
+
+/// Version of the persisted note format written by this build.
+///
+/// * `0` - a bare json array of notes, as written before the format was versioned.
+///   Depending on the build that wrote it, colors have 3 (rgb) or 4 (rgba) channels.
+/// * `1` - `{ "version": 1, "notes": [...] }`, colors are always rgb.
+/// * `2` - ids are ULID strings instead of random `i32`s.
+/// * `3` - the RFC 2822 `date_last_edited` string is replaced by RFC 3339 `created` and `modified` timestamps.
+pub const SCHEMA_VERSION: u64 = 3;
+
+// upgrades a single note by one version, or tells why it can not
+type Migration = fn(Map<String, Value>) -> Result<Map<String, Value>, String>;
+
+// migrations[n] upgrades a single note from version n to n + 1
+const MIGRATIONS: &[Migration] = &[
+    v0_to_v1,
+    v1_to_v2,
+    v2_to_v3,
+];
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u64,
+    notes: &'a [Note],
+}
+
+/// Loads the notes from the json data file `file`, upgrading older formats and repairing
+/// duplicate ids on the way. A changed file is written back right away, the original is kept as a backup.
+/// While the notes are read-only (see [`is_read_only`]) the upgrade only happens in memory.
+pub fn load_notes(file: &str) -> Result<Vec<Note>, StorageError> {
+    let path = data_path(file)?;
+    let (version, mut notes) = upgrade_collection(read_json(&path)?, &path)?;
+    let repaired = repair_ids(&mut notes);
+
+    if (version < SCHEMA_VERSION || !repaired.is_empty()) && !is_read_only() {
+        info!("[Snow]: upgrading '{}' from version {} to {}", path.display(), version, SCHEMA_VERSION);
+        backup::rotate(file, &path, true)?;
+        save_notes(file, &notes)?;
+    }
+    Ok(notes)
+}
+
+/// Like [`load_notes`], but a missing file is treated as a first run and an empty store is created.
+pub fn load_or_init_notes(file: &str) -> Result<Vec<Note>, StorageError> {
+    match load_notes(file) {
+        // the copy of the app that owns the data dir creates it
+        Err(StorageError::Missing(_)) if is_read_only() => Ok(Vec::new()),
+        Err(StorageError::Missing(path)) => {
+            info!("[Snow]: no data found at '{}', creating an empty store", path.display());
+            save_notes(file, &[])?;
+            Ok(Vec::new())
+        }
+        result => result,
+    }
+}
+
+pub fn save_notes(file: &str, notes: &[Note]) -> Result<(), StorageError> {
+    save_file(file, Envelope { version: SCHEMA_VERSION, notes })
+}
+
+/// Reads a whole collection in any known version. Returns the version it was stored in.
+pub fn upgrade_collection(value: Value, path: &Path) -> Result<(u64, Vec<Note>), StorageError> {
+    let corrupt = |reason: String| StorageError::Corrupt { path: path.to_path_buf(), source: reason.into() };
+
+    let (version, notes) = match value {
+        Value::Array(notes) => (0, notes),
+        Value::Object(mut envelope) => {
+            let version = envelope.get("version")
+                .and_then(Value::as_u64)
+                .ok_or_else(|| corrupt("missing schema version".to_string()))?;
+            match envelope.remove("notes") {
+                Some(Value::Array(notes)) => (version, notes),
+                _ => return Err(corrupt("missing notes".to_string())),
+            }
+        }
+        _ => return Err(corrupt("expected a list of notes".to_string())),
+    };
+
+    if version > SCHEMA_VERSION {
+        return Err(StorageError::UnsupportedVersion { path: path.to_path_buf(), version });
+    }
+    let notes = notes.into_iter()
+        .map(|note| upgrade_note(note, version, path))
+        .collect::<Result<Vec<Note>, StorageError>>()?;
+    Ok((version, notes))
+}
+
+/// Brings a single note stored in `version` up to the current format.
+pub fn upgrade_note(note: Value, version: u64, path: &Path) -> Result<Note, StorageError> {
+    if version > SCHEMA_VERSION {
+        return Err(StorageError::UnsupportedVersion { path: path.to_path_buf(), version });
+    }
+    let corrupt = |reason: String| StorageError::Corrupt { path: path.to_path_buf(), source: reason.into() };
+
+    let mut fields = match note {
+        Value::Object(fields) => fields,
+        _ => return Err(corrupt("expected a note object".to_string())),
+    };
+    for migration in &MIGRATIONS[version as usize..] {
+        fields = migration(fields).map_err(&corrupt)?;
+    }
+    serde_json::from_value(Value::Object(fields)).map_err(|e| corrupt(e.to_string()))
+}
+
+// rgba colors lose their alpha channel
+fn v0_to_v1(mut note: Map<String, Value>) -> Result<Map<String, Value>, String> {
+    match note.get_mut("color") {
+        Some(Value::Array(channels)) if channels.len() >= 3 => channels.truncate(3),
+        _ => return Err("note without a valid color".to_string()),
+    }
+    Ok(note)
+}
+
+// legacy i32 ids are mapped to ulids, see `NoteId::from_legacy`
+fn v1_to_v2(mut note: Map<String, Value>) -> Result<Map<String, Value>, String> {
+    let legacy = note.get("id")
+        .and_then(Value::as_i64)
+        .and_then(|id| i32::try_from(id).ok())
+        .ok_or_else(|| "note without a valid id".to_string())?;
+    note.insert("id".to_string(), Value::String(NoteId::from_legacy(legacy).to_string()));
+    Ok(note)
+}
+
+// the old timestamp was only ever set on creation, so it is the best guess for both new ones
+fn v2_to_v3(mut note: Map<String, Value>) -> Result<Map<String, Value>, String> {
+    let date = note.remove("date_last_edited")
+        .and_then(|date| date.as_str().map(parse_legacy_date))
+        .unwrap_or_else(Utc::now);
+    note.insert("created".to_string(), Value::String(date.to_rfc3339()));
+    note.insert("modified".to_string(), Value::String(date.to_rfc3339()));
+    Ok(note)
+}
+
+/// Parses the RFC 2822 `date_last_edited` strings of older versions,
+/// anything unreadable is treated as "now".
+pub fn parse_legacy_date(date: &str) -> DateTime<Utc> {
+    match DateTime::parse_from_rfc2822(date) {
+        Ok(date) => date.with_timezone(&Utc),
+        Err(e) => {
+            warn!("[Snow]: could not read the date '{}' ({}), using the current time", date, e);
+            Utc::now()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V0: &str = include_str!("../../tests/fixtures/data-v0.json");
+    const V1: &str = include_str!("../../tests/fixtures/data-v1.json");
+    const V2: &str = include_str!("../../tests/fixtures/data-v2.json");
+
+    fn upgrade(json: &str) -> (u64, Vec<Note>) {
+        upgrade_collection(serde_json::from_str(json).unwrap(), Path::new("data.json")).unwrap()
+    }
+
+    #[test]
+    fn bare_arrays_are_version_0() {
+        let (version, notes) = upgrade(V0);
+        assert_eq!(version, 0);
+        let titles: Vec<(&str, [u8; 3])> = notes.iter().map(|note| (note.title.as_str(), note.color)).collect();
+        assert_eq!(titles, vec![("Groceries", [255, 0, 0]), ("Ideas ✨", [12, 34, 56])]);
+        assert_eq!(notes[0].text, "milk\neggs\n- [ ] bread");
+    }
+
+    #[test]
+    fn v0_and_v1_end_up_the_same() {
+        let (version, notes) = upgrade(V1);
+        assert_eq!(version, 1);
+        assert_eq!(notes, upgrade(V0).1);
+    }
+
+    #[test]
+    fn rgba_colors_lose_their_alpha() {
+        let note = |color: Value| serde_json::json!({ "color": color }).as_object().unwrap().clone();
+        assert_eq!(v0_to_v1(note(serde_json::json!([1, 2, 3, 4]))).unwrap()["color"], serde_json::json!([1, 2, 3]));
+        assert_eq!(v0_to_v1(note(serde_json::json!([1, 2, 3]))).unwrap()["color"], serde_json::json!([1, 2, 3]));
+        assert!(v0_to_v1(note(serde_json::json!([1, 2]))).is_err());
+    }
+
+    #[test]
+    fn current_version_round_trips() {
+        let notes = upgrade(V1).1;
+        let json = serde_json::to_value(Envelope { version: SCHEMA_VERSION, notes: &notes }).unwrap();
+        assert_eq!(upgrade_collection(json, Path::new("data.json")).unwrap(), (SCHEMA_VERSION, notes));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let json = serde_json::json!({ "version": SCHEMA_VERSION + 1, "notes": [] });
+        let result = upgrade_collection(json, Path::new("data.json"));
+        assert!(matches!(result, Err(StorageError::UnsupportedVersion { version, .. }) if version == SCHEMA_VERSION + 1));
+    }
+
+    #[test]
+    fn rejects_files_without_a_version_or_notes() {
+        for json in [serde_json::json!({ "notes": [] }), serde_json::json!({ "version": 1 }), serde_json::json!("notes")] {
+            let result = upgrade_collection(json, Path::new("data.json"));
+            assert!(matches!(result, Err(StorageError::Corrupt { .. })));
+        }
+    }
+
+    #[test]
+    fn legacy_ids_become_ulids() {
+        let legacy = vec![NoteId::from_legacy(1234), NoteId::from_legacy(-7)];
+        let ids = |json| upgrade(json).1.into_iter().map(|note| note.id).collect::<Vec<_>>();
+        assert_eq!(ids(V1), legacy);
+        // what version 2 files hold for the same notes
+        assert_eq!(ids(V2), legacy);
+        assert_eq!(upgrade(V2).0, 2);
+
+        let note = |id: Value| serde_json::json!({ "id": id }).as_object().unwrap().clone();
+        assert!(v1_to_v2(note(serde_json::json!(i64::from(i32::MAX) + 1))).is_err());
+        assert!(v1_to_v2(note(serde_json::json!("1234"))).is_err());
+    }
+
+    // what every fixture holds once it is on the current version
+    fn expected() -> Vec<Note> {
+        let date = |date| DateTime::parse_from_rfc3339(date).unwrap().with_timezone(&Utc);
+        let groceries = date("2022-03-01T09:30:00Z");
+        let ideas = date("2022-03-02T17:05:10Z");
+        vec![
+            Note {
+                id: NoteId::from_legacy(1234),
+                title: "Groceries".to_string(),
+                text: "milk\neggs\n- [ ] bread".to_string(),
+                created: groceries,
+                modified: groceries,
+                color: [255, 0, 0],
+                deleted: None,
+                revisions: Vec::new(),
+            },
+            Note {
+                id: NoteId::from_legacy(-7),
+                title: "Ideas ✨".to_string(),
+                text: String::new(),
+                created: ideas,
+                modified: ideas,
+                color: [12, 34, 56],
+                deleted: None,
+                revisions: Vec::new(),
+            },
+        ]
+    }
+
+    #[test]
+    fn upgrades_every_version() {
+        assert_eq!(upgrade(V0), (0, expected()));
+        assert_eq!(upgrade(V1), (1, expected()));
+        assert_eq!(upgrade(V2), (2, expected()));
+    }
+
+    #[test]
+    fn legacy_dates_become_timestamps() {
+        let note = |date: &str| serde_json::json!({ "date_last_edited": date }).as_object().unwrap().clone();
+        let upgraded = v2_to_v3(note("Wed, 02 Mar 2022 18:05:10 +0100")).unwrap();
+        assert_eq!(upgraded.get("date_last_edited"), None);
+        assert_eq!(upgraded["created"], serde_json::json!("2022-03-02T17:05:10+00:00"));
+        assert_eq!(upgraded["modified"], upgraded["created"]);
+
+        // unreadable dates are taken as now
+        let before = Utc::now();
+        assert!(parse_legacy_date("yesterday") >= before);
+    }
+}
@@ -0,0 +1,377 @@
+use super::{data_path, is_read_only, load_notes, parse_legacy_date, NoteId, NoteMeta, NoteStore, StorageError};
+use crate::Note;
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Row, ToSql, Transaction};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Stores notes as rows of an embedded sqlite database, so a change only
+/// touches the affected row instead of rewriting every note.
+pub struct SqliteStore {
+    conn: Connection,
+    path: PathBuf,
+}
+
+// the tables as they were first created, only used for new databases, later changes are applied by `MIGRATIONS`
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS notes (
+        id INTEGER PRIMARY KEY,
+        title TEXT NOT NULL,
+        text TEXT NOT NULL,
+        date_last_edited TEXT NOT NULL,
+        color BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS notes_last_edited ON notes (date_last_edited);
+    CREATE INDEX IF NOT EXISTS notes_title ON notes (title);
+";
+
+// MIGRATIONS[n] brings the database from `PRAGMA user_version` n to n + 1
+const MIGRATIONS: &[fn(&Transaction) -> rusqlite::Result<()>] = &[
+    ulid_ids,
+    timestamps,
+    trash,
+    revisions,
+];
+
+impl SqliteStore {
+    /// Opens the database at `path`, creating and upgrading it as needed. While the notes are
+    /// read-only (see [`is_read_only`]) the database has to exist and be up to date already.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, StorageError> {
+        let path = path.into();
+        if is_read_only() {
+            let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .map_err(|e| StorageError::database(&path, e))?;
+            let version = user_version(&conn, &path)?;
+            if version != MIGRATIONS.len() {
+                return Err(StorageError::ReadOnly(path));
+            }
+            return Ok(SqliteStore { conn, path });
+        }
+        let mut conn = Connection::open(&path).map_err(|e| StorageError::database(&path, e))?;
+        // the tables of version 0, the migrations take it from there
+        if user_version(&conn, &path)? == 0 {
+            conn.execute_batch(SCHEMA).map_err(|e| StorageError::database(&path, e))?;
+        }
+        migrate(&mut conn, &path)?;
+        Ok(SqliteStore { conn, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// One-shot import of the notes in the json data file `file`. Only happens while the
+    /// database is still empty, afterwards the json file is renamed to `<file>.json.migrated`
+    /// so it is neither imported twice nor lost.
+    pub fn import_json(&mut self, file: &str) -> Result<usize, StorageError> {
+        let json = data_path(file)?;
+        if is_read_only() || !json.exists() || self.count()? > 0 {
+            return Ok(0);
+        }
+
+        let notes = load_notes(file)?;
+        self.save_all(&notes)?;
+
+        let mut migrated = json.clone();
+        migrated.set_extension("json.migrated");
+        fs::rename(&json, &migrated).map_err(|e| StorageError::writing(&json, e))?;
+        info!("[Snow]: migrated {} notes from '{}' to '{}'", notes.len(), json.display(), self.path.display());
+        Ok(notes.len())
+    }
+
+    fn count(&self) -> Result<usize, StorageError> {
+        self.conn.query_row("SELECT COUNT(*) FROM notes", [], |row| row.get::<_, i64>(0))
+            .map(|count| count as usize)
+            .map_err(|e| StorageError::database(&self.path, e))
+    }
+
+    fn note_from_row(row: &Row) -> rusqlite::Result<Note> {
+        Ok(Note {
+            id: row.get("id")?,
+            title: row.get("title")?,
+            text: row.get("text")?,
+            created: from_millis(row.get("created")?),
+            modified: from_millis(row.get("modified")?),
+            color: color_from_blob(row.get("color")?),
+            deleted: row.get::<_, Option<i64>>("deleted")?.map(from_millis),
+            revisions: match row.get::<_, Option<String>>("revisions")? {
+                Some(json) => serde_json::from_str(&json)
+                    .map_err(|e| rusqlite::Error::FromSqlConversionFailure(7, rusqlite::types::Type::Text, Box::new(e)))?,
+                None => Vec::new(),
+            },
+        })
+    }
+
+    fn insert(conn: &Connection, note: &Note) -> rusqlite::Result<usize> {
+        let revisions = match note.revisions.is_empty() {
+            true => None,
+            false => Some(serde_json::to_string(&note.revisions)
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?),
+        };
+        conn.execute(
+            "INSERT INTO notes (id, title, text, created, modified, color, deleted, revisions) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT (id) DO UPDATE SET
+                title = excluded.title,
+                text = excluded.text,
+                created = excluded.created,
+                modified = excluded.modified,
+                color = excluded.color,
+                deleted = excluded.deleted,
+                revisions = excluded.revisions",
+            params![
+                note.id,
+                note.title,
+                note.text,
+                note.created.timestamp_millis(),
+                note.modified.timestamp_millis(),
+                &note.color[..],
+                note.deleted.map(|deleted| deleted.timestamp_millis()),
+                revisions,
+            ],
+        )
+    }
+}
+
+impl NoteStore for SqliteStore {
+    fn load_all(&mut self) -> Result<Vec<Note>, StorageError> {
+        let mut stmt = self.conn.prepare("SELECT * FROM notes ORDER BY id")
+            .map_err(|e| StorageError::database(&self.path, e))?;
+        let notes = stmt.query_map([], Self::note_from_row)
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<Note>>>())
+            .map_err(|e| StorageError::database(&self.path, e))?;
+        Ok(notes)
+    }
+
+    fn get(&self, id: NoteId) -> Result<Option<Note>, StorageError> {
+        self.conn.query_row("SELECT * FROM notes WHERE id = ?1", [id], Self::note_from_row)
+            .optional()
+            .map_err(|e| StorageError::database(&self.path, e))
+    }
+
+    fn upsert(&mut self, note: &Note) -> Result<(), StorageError> {
+        Self::insert(&self.conn, note)
+            .map(|_| ())
+            .map_err(|e| StorageError::database(&self.path, e))
+    }
+
+    fn delete(&mut self, id: NoteId) -> Result<bool, StorageError> {
+        self.conn.execute("DELETE FROM notes WHERE id = ?1", [id])
+            .map(|deleted| deleted > 0)
+            .map_err(|e| StorageError::database(&self.path, e))
+    }
+
+    fn list(&self) -> Result<Vec<NoteMeta>, StorageError> {
+        let mut stmt = self.conn
+            .prepare("SELECT id, title, created, modified, color FROM notes ORDER BY modified DESC")
+            .map_err(|e| StorageError::database(&self.path, e))?;
+        let metas = stmt
+            .query_map([], |row| {
+                Ok(NoteMeta {
+                    id: row.get("id")?,
+                    title: row.get("title")?,
+                    created: from_millis(row.get("created")?),
+                    modified: from_millis(row.get("modified")?),
+                    color: color_from_blob(row.get("color")?),
+                })
+            })
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<NoteMeta>>>())
+            .map_err(|e| StorageError::database(&self.path, e))?;
+        Ok(metas)
+    }
+
+    fn location(&self) -> Option<PathBuf> {
+        Some(self.path.clone())
+    }
+
+    // replaces everything in a single transaction
+    fn save_all(&mut self, notes: &[Note]) -> Result<(), StorageError> {
+        let path = self.path.clone();
+        let tx = self.conn.transaction().map_err(|e| StorageError::database(&path, e))?;
+        tx.execute("DELETE FROM notes", []).map_err(|e| StorageError::database(&path, e))?;
+        for note in notes {
+            Self::insert(&tx, note).map_err(|e| StorageError::database(&path, e))?;
+        }
+        tx.commit().map_err(|e| StorageError::database(&path, e))
+    }
+}
+
+// colors are stored as a 3 byte blob
+fn color_from_blob(blob: Vec<u8>) -> [u8; 3] {
+    let mut color = [0; 3];
+    for (channel, value) in color.iter_mut().zip(blob) {
+        *channel = value;
+    }
+    color
+}
+
+fn user_version(conn: &Connection, path: &Path) -> Result<usize, StorageError> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))
+        .map(|version| version as usize)
+        .map_err(|e| StorageError::database(path, e))
+}
+
+fn migrate(conn: &mut Connection, path: &Path) -> Result<(), StorageError> {
+    let version = user_version(conn, path)?;
+    if version > MIGRATIONS.len() {
+        return Err(StorageError::UnsupportedVersion { path: path.to_path_buf(), version: version as u64 });
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        info!("[Snow]: upgrading '{}' from version {} to {}", path.display(), from, from + 1);
+        let tx = conn.transaction().map_err(|e| StorageError::database(path, e))?;
+        migration(&tx)
+            .and_then(|_| tx.pragma_update(None, "user_version", (from + 1) as i64))
+            .map_err(|e| StorageError::database(path, e))?;
+        tx.commit().map_err(|e| StorageError::database(path, e))?;
+    }
+    Ok(())
+}
+
+// integer ids become ulid strings, see `NoteId::from_legacy`
+fn ulid_ids(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("
+        CREATE TABLE notes_ulid (
+            id TEXT PRIMARY KEY NOT NULL,
+            title TEXT NOT NULL,
+            text TEXT NOT NULL,
+            date_last_edited TEXT NOT NULL,
+            color BLOB NOT NULL
+        );
+    ")?;
+    {
+        let mut select = tx.prepare("SELECT id, title, text, date_last_edited, color FROM notes")?;
+        let mut insert = tx.prepare("INSERT INTO notes_ulid VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        let mut rows = select.query([])?;
+        while let Some(row) = rows.next()? {
+            insert.execute(params![
+                NoteId::from_legacy(row.get(0)?),
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, Vec<u8>>(4)?,
+            ])?;
+        }
+    }
+    tx.execute_batch("
+        DROP TABLE notes;
+        ALTER TABLE notes_ulid RENAME TO notes;
+        CREATE INDEX notes_last_edited ON notes (date_last_edited);
+        CREATE INDEX notes_title ON notes (title);
+    ")
+}
+
+// the RFC 2822 `date_last_edited` strings become `created` and `modified` unix timestamps in milliseconds
+fn timestamps(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("
+        CREATE TABLE notes_timestamps (
+            id TEXT PRIMARY KEY NOT NULL,
+            title TEXT NOT NULL,
+            text TEXT NOT NULL,
+            created INTEGER NOT NULL,
+            modified INTEGER NOT NULL,
+            color BLOB NOT NULL
+        );
+    ")?;
+    {
+        let mut select = tx.prepare("SELECT id, title, text, date_last_edited, color FROM notes")?;
+        let mut insert = tx.prepare("INSERT INTO notes_timestamps VALUES (?1, ?2, ?3, ?4, ?4, ?5)")?;
+        let mut rows = select.query([])?;
+        while let Some(row) = rows.next()? {
+            insert.execute(params![
+                row.get::<_, NoteId>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                parse_legacy_date(&row.get::<_, String>(3)?).timestamp_millis(),
+                row.get::<_, Vec<u8>>(4)?,
+            ])?;
+        }
+    }
+    tx.execute_batch("
+        DROP TABLE notes;
+        ALTER TABLE notes_timestamps RENAME TO notes;
+        CREATE INDEX notes_modified ON notes (modified);
+        CREATE INDEX notes_created ON notes (created);
+        CREATE INDEX notes_title ON notes (title);
+    ")
+}
+
+// notes in the trash have the time they were deleted at, in milliseconds
+fn trash(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE notes ADD COLUMN deleted INTEGER;")
+}
+
+// the revision history of a note, as a json array
+fn revisions(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE notes ADD COLUMN revisions TEXT;")
+}
+
+fn from_millis(millis: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(millis).single().unwrap_or_else(Utc::now)
+}
+
+impl ToSql for NoteId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for NoteId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh database file that is removed again once the test is done
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new() -> Self {
+            TempDb(std::env::temp_dir().join(format!("snow-treading-{}.sqlite3", NoteId::new())))
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn reopens_an_upgraded_database() {
+        let db = TempDb::new();
+        let mut note = Note::new(NoteId::new(), "text".to_string(), "title".to_string(), [1, 2, 3]);
+        // the database keeps milliseconds
+        note.created = from_millis(note.created.timestamp_millis());
+        note.modified = note.created;
+        SqliteStore::open(&db.0).unwrap().save_all(&[note.clone()]).unwrap();
+
+        let mut store = SqliteStore::open(&db.0).unwrap();
+        assert_eq!(store.load_all().unwrap(), vec![note]);
+    }
+
+    #[test]
+    fn upgrades_a_version_0_database() {
+        let db = TempDb::new();
+        let conn = Connection::open(&db.0).unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        conn.execute(
+            "INSERT INTO notes VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![1234, "title", "text", "Tue, 01 Mar 2022 09:30:00 +0000", &[1u8, 2, 3][..]],
+        ).unwrap();
+        drop(conn);
+
+        let notes = SqliteStore::open(&db.0).unwrap().load_all().unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].id, NoteId::from_legacy(1234));
+        assert_eq!((notes[0].title.as_str(), notes[0].text.as_str(), notes[0].color), ("title", "text", [1, 2, 3]));
+        assert_eq!(notes[0].modified, DateTime::parse_from_rfc2822("Tue, 01 Mar 2022 09:30:00 +0000").unwrap());
+
+        // and opens again once it is up to date
+        assert_eq!(SqliteStore::open(&db.0).unwrap().load_all().unwrap(), notes);
+    }
+}
@@ -0,0 +1,68 @@
+use super::StorageError;
+use crate::{Note, NoteId};
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+
+/// The part of a note that is needed for listings, without the (possibly large) text.
+#[derive(Clone, Debug)]
+pub struct NoteMeta {
+    pub id: NoteId,
+    pub title: String,
+    pub created: DateTime<Utc>,
+    pub modified: DateTime<Utc>,
+    pub color: [u8; 3],
+}
+
+impl From<&Note> for NoteMeta {
+    fn from(note: &Note) -> Self {
+        NoteMeta {
+            id: note.id,
+            title: note.title.clone(),
+            created: note.created,
+            modified: note.modified,
+            color: note.color,
+        }
+    }
+}
+
+/// A place notes are persisted to. The app only ever talks to this trait,
+/// so backends can be swapped without touching any UI code.
+pub trait NoteStore: Send {
+    /// Reads every note from the backend, replacing whatever was cached before.
+    fn load_all(&mut self) -> Result<Vec<Note>, StorageError>;
+
+    fn get(&self, id: NoteId) -> Result<Option<Note>, StorageError>;
+
+    /// Inserts the note, or replaces the stored note with the same id.
+    fn upsert(&mut self, note: &Note) -> Result<(), StorageError>;
+
+    /// Returns whether there was a note with this id.
+    fn delete(&mut self, id: NoteId) -> Result<bool, StorageError>;
+
+    fn list(&self) -> Result<Vec<NoteMeta>, StorageError>;
+
+    /// The file or directory the notes live in, so it can be watched for changes
+    /// made by other programs. `None` if they do not live on disk.
+    fn location(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Whether the notes may have been changed by someone else since this store last read
+    /// or wrote them, so our own writes are not reloaded. Stores that can not tell always say yes.
+    fn changed_externally(&self) -> bool {
+        true
+    }
+
+    /// Makes the store contain exactly `notes`.
+    fn save_all(&mut self, notes: &[Note]) -> Result<(), StorageError> {
+        for stale in self.list()? {
+            if !notes.iter().any(|note| note.id == stale.id) {
+                self.delete(stale.id)?;
+            }
+        }
+        for note in notes {
+            self.upsert(note)?;
+        }
+        Ok(())
+    }
+}
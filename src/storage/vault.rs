@@ -0,0 +1,315 @@
+use super::{fnv1a, is_read_only, parse_legacy_date, read_json, write_atomic, write_json, NoteId, NoteMeta, NoteStore, StorageError};
+use crate::Note;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use ulid::Ulid;
+
+const DELIMITER: &str = "---\n";
+
+/// Keeps every note as a plain `<title-slug>.md` file inside a directory, with the
+/// fields that are not part of the text stored as yaml front matter:
+///
+/// ```text
+/// ---
+/// id: 01FX8R4ZQ5N6V3K0T7W2B9JHME
+/// title: Groceries
+/// color: [255, 0, 0]
+/// created: "2022-03-01T09:00:00Z"
+/// modified: "2022-03-01T09:30:00Z"
+/// ---
+/// the actual note text
+/// ```
+///
+/// Markdown files without our front matter (e.g. created with another editor) are picked up
+/// as well, they get their title from the file name and the front matter on the next save.
+/// Notes in the trash are moved to a `.trash` folder inside the vault, the revision
+/// history of a note is kept next to it in `.history/<id>.json`.
+pub struct VaultStore {
+    dir: PathBuf,
+    // which file each note currently lives in, needed to follow renames
+    files: HashMap<NoteId, PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FrontMatter {
+    id: NoteId,
+    title: String,
+    color: [u8; 3],
+    // missing in files written before notes had real timestamps
+    created: Option<DateTime<Utc>>,
+    modified: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing)]
+    date_last_edited: Option<String>,
+}
+
+const TRASH_DIR: &str = ".trash";
+const HISTORY_DIR: &str = ".history";
+
+impl VaultStore {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, StorageError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| StorageError::writing(&dir, e))?;
+        let mut store = VaultStore { dir, files: HashMap::new() };
+        store.read_all()?;
+        Ok(store)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn history_file(&self, id: NoteId) -> PathBuf {
+        self.dir.join(HISTORY_DIR).join(format!("{}.json", id))
+    }
+
+    fn read_history(&self, note: &mut Note) -> Result<(), StorageError> {
+        let path = self.history_file(note.id);
+        if path.exists() {
+            note.revisions = read_json(&path)?;
+        }
+        Ok(())
+    }
+
+    // notes without revisions have no history file
+    fn write_history(&self, note: &Note) -> Result<(), StorageError> {
+        let path = self.history_file(note.id);
+        if !note.revisions.is_empty() {
+            let dir = self.dir.join(HISTORY_DIR);
+            fs::create_dir_all(&dir).map_err(|e| StorageError::writing(&dir, e))?;
+            return write_json(&path, &note.revisions);
+        }
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(StorageError::writing(&path, e)),
+            _ => Ok(()),
+        }
+    }
+
+    // reads every note in the vault and its trash and rebuilds the file index
+    fn read_all(&mut self) -> Result<Vec<Note>, StorageError> {
+        let mut paths = Vec::new();
+        for dir in [self.dir.clone(), self.dir.join(TRASH_DIR)] {
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&dir).map_err(|e| StorageError::reading(&dir, e))? {
+                let path = entry.map_err(|e| StorageError::reading(&dir, e))?.path();
+                if path.is_file() && path.extension().is_some_and(|ext| ext == "md") {
+                    paths.push(path);
+                }
+            }
+        }
+        paths.sort();
+
+        self.files.clear();
+        let mut notes = Vec::with_capacity(paths.len());
+        for path in paths {
+            let mut note = read_note(&path)?;
+            // e.g. a file that was copied by hand, the copy becomes a note of its own
+            if let Some(other) = self.files.get(&note.id) {
+                let id = NoteId::new();
+                warn!("[Snow]: '{}' and '{}' share the id {}, the latter gets the new id {}", other.display(), path.display(), note.id, id);
+                note.id = id;
+                if !is_read_only() {
+                    write_atomic(&path, render_note(&note, &path)?.as_bytes())?;
+                }
+            }
+            self.read_history(&mut note)?;
+            self.files.insert(note.id, path);
+            notes.push(note);
+        }
+        Ok(notes)
+    }
+
+    fn dir_for(&self, note: &Note) -> PathBuf {
+        if note.is_deleted() {
+            self.dir.join(TRASH_DIR)
+        } else {
+            self.dir.clone()
+        }
+    }
+
+    // picks `<slug>.md`, or `<slug>-<id>.md` if another note already has that name
+    fn file_for(&self, note: &Note) -> PathBuf {
+        let slug = slugify(&note.title);
+        let dir = self.dir_for(note);
+        let path = dir.join(format!("{}.md", slug));
+        let taken = self.files.iter().any(|(id, file)| *id != note.id && *file == path);
+        if taken || (path.exists() && self.files.get(&note.id) != Some(&path)) {
+            dir.join(format!("{}-{}.md", slug, note.id))
+        } else {
+            path
+        }
+    }
+}
+
+impl NoteStore for VaultStore {
+    fn load_all(&mut self) -> Result<Vec<Note>, StorageError> {
+        self.read_all()
+    }
+
+    fn get(&self, id: NoteId) -> Result<Option<Note>, StorageError> {
+        match self.files.get(&id) {
+            Some(path) => {
+                let mut note = read_note(path)?;
+                self.read_history(&mut note)?;
+                Ok(Some(note))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn upsert(&mut self, note: &Note) -> Result<(), StorageError> {
+        let old = self.files.get(&note.id).cloned();
+        let dir = self.dir_for(note);
+        let path = match &old {
+            // keep the file as long as the title still maps to it and the note was not (un)trashed
+            Some(old) if old.parent() == Some(dir.as_path()) && old.file_stem().and_then(|stem| stem.to_str()).is_some_and(|stem| {
+                stem == slugify(&note.title) || stem == format!("{}-{}", slugify(&note.title), note.id)
+            }) => old.clone(),
+            _ => self.file_for(note),
+        };
+        fs::create_dir_all(&dir).map_err(|e| StorageError::writing(&dir, e))?;
+
+        write_atomic(&path, render_note(note, &path)?.as_bytes())?;
+        self.write_history(note)?;
+
+        // the title changed or the note moved in or out of the trash, so it got a new file
+        if let Some(old) = old.filter(|old| *old != path) {
+            match fs::remove_file(&old) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(StorageError::writing(&old, e)),
+                _ => debug!("renamed '{}' to '{}'", old.display(), path.display()),
+            }
+        }
+        self.files.insert(note.id, path);
+        Ok(())
+    }
+
+    fn delete(&mut self, id: NoteId) -> Result<bool, StorageError> {
+        let path = match self.files.remove(&id) {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        let history = self.history_file(id);
+        if let Err(e) = fs::remove_file(&history) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(StorageError::writing(&history, e));
+            }
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(StorageError::writing(&path, e)),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<NoteMeta>, StorageError> {
+        self.files.values().map(|path| read_note(path).map(|note| NoteMeta::from(&note))).collect()
+    }
+
+    fn location(&self) -> Option<PathBuf> {
+        Some(self.dir.clone())
+    }
+}
+
+fn read_note(path: &Path) -> Result<Note, StorageError> {
+    let contents = fs::read_to_string(path).map_err(|e| StorageError::reading(path, e))?;
+
+    let front_matter = contents.strip_prefix(DELIMITER)
+        .and_then(|rest| rest.find(&format!("\n{}", DELIMITER)).map(|end| (rest, end)))
+        // front matter of other tools (obsidian, jekyll, ...) has none of our fields,
+        // such files are read like plain markdown and keep it as part of the text
+        .and_then(|(rest, end)| match serde_yaml::from_str::<FrontMatter>(&rest[..end]) {
+            Ok(meta) => Some((meta, &rest[end + 1 + DELIMITER.len()..])),
+            Err(e) => {
+                info!("[Snow]: '{}' has front matter that is not ours: {}", path.display(), e);
+                None
+            }
+        });
+    match front_matter {
+        Some((meta, text)) => {
+            let fallback = meta.date_last_edited.as_deref().map(parse_legacy_date)
+                .unwrap_or_else(|| file_modified(path));
+            Ok(Note {
+                id: meta.id,
+                title: meta.title,
+                text: text.to_string(),
+                created: meta.created.unwrap_or(fallback),
+                modified: meta.modified.unwrap_or(fallback),
+                color: meta.color,
+                deleted: meta.deleted,
+                revisions: Vec::new(),
+            })
+        }
+        // a plain markdown file that was not written by us
+        None => {
+            let title = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+            let mut note = Note::new(id_from_path(path), contents, title, [0, 0, 0]);
+            note.created = file_modified(path);
+            note.modified = note.created;
+            Ok(note)
+        }
+    }
+}
+
+fn render_note(note: &Note, path: &Path) -> Result<String, StorageError> {
+    let meta = FrontMatter {
+        id: note.id,
+        title: note.title.clone(),
+        color: note.color,
+        created: Some(note.created),
+        modified: Some(note.modified),
+        deleted: note.deleted,
+        date_last_edited: None,
+    };
+    let yaml = serde_yaml::to_string(&meta)
+        .map_err(|e| StorageError::writing(path, io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    let yaml = yaml.strip_prefix(DELIMITER).unwrap_or(&yaml);
+
+    let mut contents = String::with_capacity(yaml.len() + note.text.len() + 2 * DELIMITER.len() + 1);
+    contents.push_str(DELIMITER);
+    contents.push_str(yaml);
+    if !yaml.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents.push_str(DELIMITER);
+    contents.push_str(&note.text);
+    Ok(contents)
+}
+
+fn file_modified(path: &Path) -> DateTime<Utc> {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .map(DateTime::<Utc>::from)
+        .unwrap_or_else(|_| Utc::now())
+}
+
+// a stable id for files without front matter, so they keep it until they are saved once
+fn id_from_path(path: &Path) -> NoteId {
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    NoteId::from(Ulid::from_parts(0, fnv1a(name.as_bytes()) as u128))
+}
+
+/// Turns a note title into something that is safe to use as a file name:
+/// lowercase letters and digits separated by single dashes.
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::with_capacity(title.len());
+    for c in title.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "untitled".to_string()
+    } else {
+        slug.chars().take(80).collect()
+    }
+}
@@ -0,0 +1,76 @@
+use super::StorageError;
+use crate::toast::Notifier;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// events for the same file closer together than this are reported once
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watches the location of a store (see [`NoteStore::location`](super::NoteStore::location))
+/// for changes, e.g. by a text editor or a second copy of the app. Our own writes are
+/// reported as well, telling them apart is up to whoever reloads the notes.
+pub struct StoreWatcher {
+    // stops watching once dropped
+    _watcher: RecommendedWatcher,
+    changed: Arc<AtomicBool>,
+}
+
+impl StoreWatcher {
+    /// `notifier` is woken up on every change, so the ui notices it without any input.
+    pub fn new(location: &Path, notifier: Notifier) -> Result<Self, StorageError> {
+        let error = |e: notify::Error| StorageError::reading(location, io::Error::other(e));
+        let (tx, rx) = mpsc::channel();
+        let mut watcher: RecommendedWatcher = Watcher::new(tx, DEBOUNCE).map_err(error)?;
+
+        // a single file is replaced on every save, so its directory is watched instead
+        let file = if location.is_dir() {
+            watcher.watch(location, RecursiveMode::Recursive).map_err(error)?;
+            None
+        } else {
+            let dir = location.parent().unwrap_or(location);
+            watcher.watch(dir, RecursiveMode::NonRecursive).map_err(error)?;
+            Some(location.to_path_buf())
+        };
+
+        let changed = Arc::new(AtomicBool::new(false));
+        let flag = changed.clone();
+        // ends when the watcher is dropped and with it the sender
+        thread::spawn(move || {
+            for event in rx {
+                if relevant(&event, file.as_deref()) {
+                    debug!("store changed: {:?}", event);
+                    flag.store(true, Ordering::SeqCst);
+                    notifier.wake();
+                }
+            }
+        });
+
+        Ok(StoreWatcher { _watcher: watcher, changed })
+    }
+
+    /// Whether anything changed since the last call.
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::SeqCst)
+    }
+}
+
+fn relevant(event: &DebouncedEvent, file: Option<&Path>) -> bool {
+    let path: &PathBuf = match event {
+        DebouncedEvent::Create(path) | DebouncedEvent::Write(path) | DebouncedEvent::Remove(path) => path,
+        DebouncedEvent::Rename(_, to) => to,
+        // the watched directory itself went away or events were lost
+        DebouncedEvent::Rescan => return true,
+        _ => return false,
+    };
+    // half written files of `write_atomic`
+    if path.extension().is_some_and(|ext| ext == "tmp") {
+        return false;
+    }
+    file.is_none_or(|file| path == file)
+}
@@ -0,0 +1,191 @@
+use super::{lock, SharedStore, StorageError};
+use crate::toast::Notifier;
+use crate::{Note, NoteId};
+use std::sync::mpsc as std_mpsc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+// jobs arriving this close to each other are written together
+const COALESCE: Duration = Duration::from_millis(100);
+
+/// A write for the [`Worker`].
+#[derive(Clone, Debug)]
+pub enum Job {
+    Upsert(Note),
+    Delete(NoteId),
+    SaveAll(Vec<Note>),
+}
+
+/// What became of a job, see [`Worker::reports`].
+#[derive(Debug)]
+pub enum Report {
+    /// the note was written in exactly this state
+    Saved(Note),
+    Deleted(NoteId),
+    Failed { id: Option<NoteId>, error: StorageError },
+    /// everything in the store, read again after someone else changed it, see [`Worker::reload`]
+    Reloaded(Vec<Note>),
+}
+
+enum Message {
+    Job(Job),
+    Reload,
+    // answered once every job sent before it was written
+    Flush(std_mpsc::Sender<()>),
+}
+
+// the jobs of one batch, only the last job for each note is kept
+#[derive(Default)]
+struct Batch {
+    all: Option<Vec<Note>>,
+    jobs: Vec<Job>,
+    // read everything again once the jobs are written
+    reload: bool,
+}
+
+impl Batch {
+    fn push(&mut self, job: Job) {
+        let id = match &job {
+            Job::Upsert(note) => note.id,
+            Job::Delete(id) => *id,
+            // replaces everything that was waiting
+            Job::SaveAll(notes) => {
+                self.all = Some(notes.clone());
+                self.jobs.clear();
+                return;
+            }
+        };
+        self.jobs.retain(|job| match job {
+            Job::Upsert(note) => note.id != id,
+            Job::Delete(other) => *other != id,
+            Job::SaveAll(_) => true,
+        });
+        self.jobs.push(job);
+    }
+
+    fn run(self, store: &SharedStore, reports: &std_mpsc::Sender<Report>, notifier: &Notifier) {
+        let mut store = lock(store);
+        let report = |result: Result<Report, (Option<NoteId>, StorageError)>| {
+            let report = result.unwrap_or_else(|(id, error)| {
+                error!("[Snow]: could not save notes: {}", error);
+                notifier.error(format!("could not save: {}", error));
+                Report::Failed { id, error }
+            });
+            // the receiver only goes away with the app
+            let _ = reports.send(report);
+        };
+
+        if let Some(notes) = self.all {
+            match store.save_all(&notes) {
+                Ok(()) => notes.into_iter().for_each(|note| report(Ok(Report::Saved(note)))),
+                Err(e) => report(Err((None, e))),
+            }
+        }
+        for job in self.jobs {
+            match job {
+                Job::Upsert(note) => report(store.upsert(&note).map(|_| Report::Saved(note.clone())).map_err(|e| (Some(note.id), e))),
+                Job::Delete(id) => report(store.delete(id).map(|_| Report::Deleted(id)).map_err(|e| (Some(id), e))),
+                Job::SaveAll(_) => unreachable!(),
+            }
+        }
+        // the watcher reports our own writes as well, those are not read again
+        if self.reload && store.changed_externally() {
+            match store.load_all() {
+                Ok(notes) => {
+                    let _ = reports.send(Report::Reloaded(notes));
+                }
+                // e.g. caught in the middle of being written, the next change tries again
+                Err(e) => warn!("[Snow]: could not reload the changed notes: {}", e),
+            }
+        }
+    }
+}
+
+/// Writes notes on a background task so the ui never waits for the disk. Jobs are written
+/// one batch after the other in the order they were sent, several jobs for the same note
+/// that arrive close together are written once.
+pub struct Worker {
+    jobs: mpsc::UnboundedSender<Message>,
+    reports: std_mpsc::Receiver<Report>,
+}
+
+impl Worker {
+    /// Starts the worker on the current tokio runtime. Failed writes are shown with `notifier`.
+    pub fn spawn(store: SharedStore, notifier: Notifier) -> Self {
+        let (jobs, rx) = mpsc::unbounded_channel();
+        let (reports_tx, reports) = std_mpsc::channel();
+        tokio::spawn(run(rx, store, reports_tx, notifier));
+        Worker { jobs, reports }
+    }
+
+    pub fn send(&self, job: Job) {
+        if self.jobs.send(Message::Job(job)).is_err() {
+            error!("[Snow]: the storage worker is gone, a write was lost");
+        }
+    }
+
+    pub fn upsert(&self, note: Note) {
+        self.send(Job::Upsert(note));
+    }
+
+    pub fn delete(&self, id: NoteId) {
+        self.send(Job::Delete(id));
+    }
+
+    pub fn save_all(&self, notes: Vec<Note>) {
+        self.send(Job::SaveAll(notes));
+    }
+
+    /// Reads the store again after the pending writes, if someone else changed it.
+    /// The notes arrive as [`Report::Reloaded`].
+    pub fn reload(&self) {
+        if self.jobs.send(Message::Reload).is_err() {
+            error!("[Snow]: the storage worker is gone, changes by other programs are not read");
+        }
+    }
+
+    /// The jobs that finished since the last call.
+    pub fn reports(&self) -> Vec<Report> {
+        self.reports.try_iter().collect()
+    }
+
+    /// Blocks until everything sent so far was written, at most for `timeout`.
+    /// Returns whether that happened in time.
+    pub fn flush(&self, timeout: Duration) -> bool {
+        let (ack, done) = std_mpsc::channel();
+        if self.jobs.send(Message::Flush(ack)).is_err() {
+            return false;
+        }
+        done.recv_timeout(timeout).is_ok()
+    }
+}
+
+async fn run(mut rx: mpsc::UnboundedReceiver<Message>, store: SharedStore, reports: std_mpsc::Sender<Report>, notifier: Notifier) {
+    while let Some(first) = rx.recv().await {
+        // give quick follow ups a moment to arrive, unless someone is waiting
+        if !matches!(first, Message::Flush(_)) {
+            tokio::time::sleep(COALESCE).await;
+        }
+
+        let mut batch = Batch::default();
+        let mut flushes = Vec::new();
+        let mut next = Some(first);
+        while let Some(message) = next {
+            match message {
+                Message::Job(job) => batch.push(job),
+                Message::Reload => batch.reload = true,
+                Message::Flush(ack) => flushes.push(ack),
+            }
+            next = rx.try_recv().ok();
+        }
+
+        let (store, reports, notifier) = (store.clone(), reports.clone(), notifier.clone());
+        let written = tokio::task::spawn_blocking(move || batch.run(&store, &reports, &notifier)).await;
+        if let Err(e) = written {
+            error!("[Snow]: the storage worker crashed while writing: {}", e);
+        }
+        for ack in flushes {
+            let _ = ack.send(());
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Small notifications ("toasts") shown in a corner of the window.
+//!
+//! A [`Notifier`] is cheap to clone and can be handed to anything that wants to tell
+//! the user something, including code running on other threads. The ui only reads
+//! the current toasts once per frame.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// older toasts are dropped once there are more than this
+const MAX_TOASTS: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+impl Level {
+    /// How long a toast of this level stays, errors stay until they are dismissed.
+    pub fn timeout(&self) -> Option<Duration> {
+        match self {
+            Level::Info | Level::Success => Some(Duration::from_secs(3)),
+            Level::Warning => Some(Duration::from_secs(6)),
+            Level::Error => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Toast {
+    pub id: u64,
+    pub level: Level,
+    pub text: String,
+    pub created: Instant,
+    pub timeout: Option<Duration>,
+}
+
+impl Toast {
+    pub fn is_expired(&self) -> bool {
+        self.timeout.is_some_and(|timeout| self.created.elapsed() >= timeout)
+    }
+}
+
+#[derive(Default)]
+struct Toasts {
+    next_id: u64,
+    toasts: Vec<Toast>,
+    // wakes up the ui when a toast is pushed from another thread
+    waker: Option<Box<dyn Fn() + Send + Sync>>,
+}
+
+/// Shared handle to the toasts that are currently shown.
+#[derive(Clone, Default)]
+pub struct Notifier {
+    inner: Arc<Mutex<Toasts>>,
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        Notifier::default()
+    }
+
+    /// Called whenever a toast is pushed, e.g. to request a repaint of the ui.
+    pub fn set_waker(&self, waker: impl Fn() + Send + Sync + 'static) {
+        self.lock().waker = Some(Box::new(waker));
+    }
+
+    /// Wakes up the ui without showing anything, e.g. because there is something new to look at.
+    pub fn wake(&self) {
+        if let Some(waker) = &self.lock().waker {
+            waker();
+        }
+    }
+
+    /// Shows `text` with the default timeout of `level`. Returns the id of the toast.
+    /// A toast that is already shown, e.g. the same error on every sync, is moved to
+    /// the end and starts its timeout again instead of being shown twice.
+    pub fn push(&self, level: Level, text: impl Into<String>) -> u64 {
+        let text = text.into();
+        let mut toasts = self.lock();
+        let shown = toasts.toasts.iter().position(|toast| toast.level == level && toast.text == text && !toast.is_expired());
+        let toast = match shown {
+            Some(index) => Toast { created: Instant::now(), ..toasts.toasts.remove(index) },
+            None => {
+                let id = toasts.next_id;
+                toasts.next_id += 1;
+                Toast { id, level, text, created: Instant::now(), timeout: level.timeout() }
+            }
+        };
+        let id = toast.id;
+        toasts.toasts.push(toast);
+        if toasts.toasts.len() > MAX_TOASTS {
+            let excess = toasts.toasts.len() - MAX_TOASTS;
+            toasts.toasts.drain(..excess);
+        }
+        if let Some(waker) = &toasts.waker {
+            waker();
+        }
+        id
+    }
+
+    pub fn info(&self, text: impl Into<String>) -> u64 {
+        self.push(Level::Info, text)
+    }
+
+    pub fn success(&self, text: impl Into<String>) -> u64 {
+        self.push(Level::Success, text)
+    }
+
+    pub fn warning(&self, text: impl Into<String>) -> u64 {
+        self.push(Level::Warning, text)
+    }
+
+    pub fn error(&self, text: impl Into<String>) -> u64 {
+        self.push(Level::Error, text)
+    }
+
+    pub fn dismiss(&self, id: u64) {
+        self.lock().toasts.retain(|toast| toast.id != id);
+    }
+
+    /// The toasts that have not expired yet, oldest first.
+    pub fn active(&self) -> Vec<Toast> {
+        let mut toasts = self.lock();
+        toasts.toasts.retain(|toast| !toast.is_expired());
+        toasts.toasts.clone()
+    }
+
+    // a panic while holding the lock can not leave the toasts in a broken state
+    fn lock(&self) -> std::sync::MutexGuard<'_, Toasts> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl fmt::Debug for Notifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notifier").field("toasts", &self.lock().toasts).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn texts(notifier: &Notifier) -> Vec<String> {
+        notifier.active().into_iter().map(|toast| toast.text).collect()
+    }
+
+    // lets the toast with `id` look like it was pushed `age` ago
+    fn age(notifier: &Notifier, id: u64, age: Duration) {
+        let mut toasts = notifier.lock();
+        let toast = toasts.toasts.iter_mut().find(|toast| toast.id == id).unwrap();
+        toast.created = Instant::now().checked_sub(age).unwrap();
+    }
+
+    #[test]
+    fn toasts_stack_oldest_first() {
+        let notifier = Notifier::new();
+        let first = notifier.info("saved");
+        let second = notifier.error("could not sync");
+        assert_ne!(first, second);
+        assert_eq!(texts(&notifier), vec!["saved", "could not sync"]);
+    }
+
+    #[test]
+    fn only_the_newest_are_kept() {
+        let notifier = Notifier::new();
+        for i in 0..MAX_TOASTS + 2 {
+            notifier.info(format!("toast {}", i));
+        }
+        let texts = texts(&notifier);
+        assert_eq!(texts.len(), MAX_TOASTS);
+        assert_eq!(texts[0], "toast 2");
+    }
+
+    #[test]
+    fn toasts_expire_except_errors() {
+        let notifier = Notifier::new();
+        let info = notifier.info("saved");
+        let warning = notifier.warning("read only");
+        let error = notifier.error("could not save");
+        for id in [info, warning, error] {
+            age(&notifier, id, Duration::from_secs(4));
+        }
+        assert_eq!(texts(&notifier), vec!["read only", "could not save"]);
+
+        age(&notifier, warning, Duration::from_secs(7));
+        assert_eq!(texts(&notifier), vec!["could not save"]);
+        notifier.dismiss(error);
+        assert!(notifier.active().is_empty());
+    }
+
+    #[test]
+    fn the_same_toast_is_shown_once() {
+        let notifier = Notifier::new();
+        let first = notifier.warning("offline");
+        notifier.info("saved");
+        age(&notifier, first, Duration::from_secs(5));
+
+        // comes back to the end with a fresh timeout
+        assert_eq!(notifier.warning("offline"), first);
+        assert_eq!(texts(&notifier), vec!["saved", "offline"]);
+        age(&notifier, first, Duration::from_secs(5));
+        assert_eq!(texts(&notifier).len(), 2);
+
+        // the same text at another level is something else
+        assert_ne!(notifier.error("offline"), first);
+        assert_eq!(texts(&notifier).len(), 3);
+    }
+
+    #[test]
+    fn pushing_wakes_the_ui() {
+        let notifier = Notifier::new();
+        let wakes = Arc::new(AtomicUsize::new(0));
+        let counter = wakes.clone();
+        notifier.set_waker(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let other = notifier.clone();
+        std::thread::spawn(move || other.success("synced")).join().unwrap();
+        notifier.wake();
+        assert_eq!(wakes.load(Ordering::SeqCst), 2);
+        assert_eq!(texts(&notifier), vec!["synced"]);
+    }
+}